@@ -0,0 +1,104 @@
+use clap::{Parser, Subcommand};
+use std::path::PathBuf;
+
+use crate::evaluator::OutputFormat;
+use crate::evaluator::workload::WorkloadMode;
+use crate::heuristics::{parse_decider, parse_scheduler};
+use crate::types::*;
+
+// Command-line options. Without a subcommand, simulates a single run
+#[derive(Debug, Parser)]
+#[command(version, about = "GPU cluster scheduling simulator")]
+#[public]
+struct Args {
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    /// Node list CSV, or Kubernetes Node manifests (YAML)
+    #[arg(long, default_value = "clusterdata/node_data/all_nodes.csv")]
+    nodes: String,
+
+    /// Pod list CSV, or Kubernetes Pod manifests (YAML)
+    #[arg(long, default_value = "clusterdata/pod_data/default.csv")]
+    pods: String,
+
+    /// Scheduling heuristic: a registered name, or a plugin pipeline such as
+    /// "dot_product*0.7+best_fit*0.3+model_penalty+gpus=fgd+ties=lru"
+    #[arg(long, default_value = "best_fit", value_parser = scheduler_spec)]
+    scheduler: String,
+
+    /// Deployment deciders, as name or name=threshold, comma separated. Deploys when any of them would.
+    /// One of: max_tasks_arrived, max_delayed, target_alloc, consecutive_failures, backlog (%), frag
+    #[arg(long, global = true, default_value = "max_tasks_arrived=10000,max_delayed=500", value_parser = decider_spec)]
+    decider: String,
+
+    /// Number of batches to average metrics over
+    #[arg(long, global = true, default_value_t = 100)]
+    batches: usize,
+
+    /// Random seed. Picked at random if not given
+    #[arg(long, global = true)]
+    seed: Option<SEED>,
+
+    /// Replay the trace in creation order instead of sampling tasks
+    #[arg(long, global = true)]
+    replay: bool,
+
+    /// Let latency-sensitive pods that do not fit evict best-effort pods
+    #[arg(long, global = true)]
+    preempt: bool,
+
+    /// Validate every placement and the cluster bookkeeping after it, stopping at the first violation
+    #[arg(long, global = true)]
+    strict: bool,
+
+    /// Output format
+    #[arg(long, global = true, value_enum, default_value_t = OutputFormat::Text)]
+    format: OutputFormat,
+
+    /// Directory to write batches.csv, summary.csv and report.json to (comparison.csv and .json for sweep)
+    #[arg(long, global = true)]
+    output: Option<PathBuf>,
+
+    /// Directory to write each batch's nodes and bound pods to, as Kubernetes manifests before they are deployed.
+    /// Sweep writes every run to its own nodes_trace_scheduler subdirectory
+    #[arg(long, global = true)]
+    export: Option<PathBuf>,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum Command {
+    /// Run every scheduler on every pod trace and node list, and compare them
+    Sweep(SweepArgs),
+}
+
+#[derive(Debug, clap::Args)]
+#[public]
+struct SweepArgs {
+    /// Directory of pod list CSVs and Pod manifests
+    #[arg(long, default_value = "clusterdata/pod_data")]
+    pod_dir: PathBuf,
+
+    /// Directory of node list CSVs and Node manifests
+    #[arg(long, default_value = "clusterdata/node_data")]
+    node_dir: PathBuf,
+
+    /// Schedulers or plugin pipelines to compare. All registered schedulers if not given
+    #[arg(long, value_delimiter = ',', value_parser = scheduler_spec)]
+    schedulers: Vec<String>,
+}
+
+fn scheduler_spec( spec: &str ) -> Result<String, String> {
+    parse_scheduler( spec ).map(|_| spec.to_string())
+}
+
+// Validate now, but keep the spec so a fresh decider can be built for every run
+fn decider_spec( spec: &str ) -> Result<String, String> {
+    parse_decider( spec ).map(|_| spec.to_string())
+}
+
+impl Args {
+    pub fn mode(&self) -> WorkloadMode {
+        if self.replay { WorkloadMode::Replay } else { WorkloadMode::Sample }
+    }
+}
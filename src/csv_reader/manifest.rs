@@ -0,0 +1,392 @@
+use std::collections::BTreeMap;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use serde::de::IntoDeserializer;
+
+use crate::error::{write_error, Error, Result};
+use crate::types::*;
+
+// Kubernetes Node and Pod manifests, as generated for the Kubernetes Scheduler Simulator
+// from the same traces as our CSV files. Only the fields the simulator models are read:
+// resource capacity and requests, and the GPU share, count and model the traces add as
+// labels, annotations and node selectors.
+// Written back the same way, with the placement in `nodeName` and the GPU index annotation.
+
+pub const GPU_MODEL_LABEL: &str = "alibabacloud.com/gpu-card-model";
+pub const GPU_COUNT: &str = "alibabacloud.com/gpu-count";
+pub const GPU_MILLI_SHARE: &str = "alibabacloud.com/gpu-milli";
+// GPU memory per GPU, as a quantity such as 8Gi
+pub const GPU_MEMORY: &str = "alibabacloud.com/gpu-mem";
+pub const QOS_LABEL: &str = "alibabacloud.com/qos";
+pub const GPU_INDEX: &str = "alibabacloud.com/gpu-index";
+
+// Exported pods run this, so a real cluster can start them
+pub const POD_IMAGE: &str = "registry.k8s.io/pause:3.9";
+
+// Whole GPUs requested the standard way, when there is no GPU-share annotation
+pub const NVIDIA_GPU: &str = "nvidia.com/gpu";
+
+#[derive(Debug, Default, PartialEq, Deserialize, Serialize)]
+#[serde(default, rename_all = "camelCase")]
+pub struct Object {
+    #[serde(skip_serializing_if = "is_default")]
+    api_version: String,
+    kind: String,
+    metadata: Metadata,
+    #[serde(skip_serializing_if = "is_default")]
+    spec: ObjectSpec,
+    #[serde(skip_serializing_if = "is_default")]
+    status: NodeStatus,
+
+    // kind: List
+    #[serde(skip_serializing_if = "is_default")]
+    items: Vec<Object>,
+}
+
+fn is_default<T: Default + PartialEq>( value: &T ) -> bool { *value == T::default() }
+
+#[derive(Debug, Default, PartialEq, Deserialize, Serialize)]
+#[serde(default)]
+struct Metadata {
+    name: String,
+    #[serde(skip_serializing_if = "is_default")]
+    labels: BTreeMap<String, String>,
+    #[serde(skip_serializing_if = "is_default")]
+    annotations: BTreeMap<String, String>,
+}
+
+// Pod spec. Node specs carry nothing we model
+#[derive(Debug, Default, PartialEq, Deserialize, Serialize)]
+#[serde(default, rename_all = "camelCase")]
+struct ObjectSpec {
+    #[serde(skip_serializing_if = "is_default")]
+    node_name: String,
+    containers: Vec<Container>,
+    #[serde(skip_serializing_if = "is_default")]
+    node_selector: BTreeMap<String, String>,
+    #[serde(skip_serializing_if = "is_default")]
+    affinity: Affinity,
+}
+
+#[derive(Debug, Default, PartialEq, Deserialize, Serialize)]
+#[serde(default)]
+struct Container {
+    #[serde(skip_serializing_if = "is_default")]
+    name: String,
+    #[serde(skip_serializing_if = "is_default")]
+    image: String,
+    resources: Resources,
+}
+
+#[derive(Debug, Default, PartialEq, Deserialize, Serialize)]
+#[serde(default)]
+struct Resources {
+    requests: BTreeMap<String, Quantity>,
+}
+
+#[derive(Debug, Default, PartialEq, Deserialize, Serialize)]
+#[serde(default, rename_all = "camelCase")]
+struct Affinity {
+    node_affinity: NodeAffinity,
+}
+
+#[derive(Debug, Default, PartialEq, Deserialize, Serialize)]
+#[serde(default)]
+struct NodeAffinity {
+    #[serde(rename = "requiredDuringSchedulingIgnoredDuringExecution")]
+    required: NodeSelector,
+}
+
+#[derive(Debug, Default, PartialEq, Deserialize, Serialize)]
+#[serde(default, rename_all = "camelCase")]
+struct NodeSelector {
+    node_selector_terms: Vec<NodeSelectorTerm>,
+}
+
+#[derive(Debug, Default, PartialEq, Deserialize, Serialize)]
+#[serde(default, rename_all = "camelCase")]
+struct NodeSelectorTerm {
+    match_expressions: Vec<Requirement>,
+}
+
+#[derive(Debug, Default, PartialEq, Deserialize, Serialize)]
+#[serde(default)]
+struct Requirement {
+    key: String,
+    operator: String,
+    values: Vec<String>,
+}
+
+#[derive(Debug, Default, PartialEq, Deserialize, Serialize)]
+#[serde(default)]
+struct NodeStatus {
+    capacity: BTreeMap<String, Quantity>,
+    allocatable: BTreeMap<String, Quantity>,
+}
+
+// Resource quantity such as "96", "500m", "768Gi" or "1e3". YAML may give plain numbers unquoted
+#[derive(Debug, Default, Clone, PartialEq)]
+struct Quantity(String);
+
+impl Serialize for Quantity {
+    fn serialize<S>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> where S: Serializer {
+        serializer.serialize_str( &self.0 )
+    }
+}
+
+impl<'de> Deserialize<'de> for Quantity {
+    fn deserialize<D>(deserializer: D) -> std::result::Result<Self, D::Error> where D: Deserializer<'de> {
+        match serde_norway::Value::deserialize(deserializer)? {
+            serde_norway::Value::String(s) => Ok(Quantity(s)),
+            serde_norway::Value::Number(n) => Ok(Quantity(n.to_string())),
+            other => Err(serde::de::Error::custom(format!("invalid quantity: {:?}", other))),
+        }
+    }
+}
+
+impl Quantity {
+    // Split "768Gi" into 768 and the multiplier of "Gi", or "1e3" into 1 and the exponent
+    fn scaled(&self, unit: f64 ) -> std::result::Result<f64, String> {
+        let s = self.0.trim();
+        let at = s.find(|c: char| c.is_ascii_alphabetic()).unwrap_or(s.len());
+        let (number, suffix) = s.split_at(at);
+
+        let number: f64 = number.parse().map_err(|_| format!("invalid quantity: {}", s))?;
+        let multiplier = match suffix {
+            "" => 1.0,
+            "n" => 1e-9, "u" => 1e-6, "m" => 1e-3,
+            "k" => 1e3, "M" => 1e6, "G" => 1e9, "T" => 1e12, "P" => 1e15, "E" => 1e18,
+            "Ki" => 1024.0, "Mi" => 1024f64.powi(2), "Gi" => 1024f64.powi(3),
+            "Ti" => 1024f64.powi(4), "Pi" => 1024f64.powi(5), "Ei" => 1024f64.powi(6),
+            _ => match suffix.strip_prefix(['e', 'E']).and_then(|exponent| exponent.parse().ok()) {
+                Some(exponent) => 10f64.powi(exponent),
+                None => return Err(format!("unsupported quantity suffix: {}", s)),
+            },
+        };
+
+        Ok(number * multiplier / unit)
+    }
+
+    fn cpu_milli(&self) -> std::result::Result<CPU, String> {
+        Ok(self.scaled( 1e-3 )?.round() as CPU)
+    }
+
+    fn memory_mib(&self) -> std::result::Result<MEM, String> {
+        Ok(self.scaled( 1024f64.powi(2) )?.round() as MEM)
+    }
+
+    fn count(&self) -> std::result::Result<u64, String> {
+        Ok(self.scaled( 1.0 )?.round() as u64)
+    }
+}
+
+fn parse_model( models: &str ) -> std::result::Result<MODEL, String> {
+    bitflags::parser::from_str( models ).map_err(|err| format!("{}, invalid GPU model: {}", err, models))
+}
+
+// Records that can be read from a manifest of the given kind
+pub trait FromManifest: Sized {
+    const KIND: &'static str;
+
+    fn from_manifest( object: &Object ) -> std::result::Result<Self, String>;
+}
+
+impl FromManifest for NodeSpecStruct {
+    const KIND: &'static str = "Node";
+
+    // Allocatable resources, or the capacity if the node does not say
+    fn from_manifest( object: &Object ) -> std::result::Result<Self, String> {
+        let status = &object.status;
+        let resource = |name: &str| status.allocatable.get(name).or_else(|| status.capacity.get(name));
+
+        let cpu_milli = resource("cpu").ok_or("no cpu capacity")?.cpu_milli()?;
+        let memory_mib = resource("memory").ok_or("no memory capacity")?.memory_mib()?;
+        let num_gpu = match resource(GPU_COUNT).or_else(|| resource(NVIDIA_GPU)) {
+            Some(count) => count.count()? as NUM,
+            None => 0,
+        };
+        let model = match object.metadata.labels.get(GPU_MODEL_LABEL) {
+            Some(model) => parse_model( model )?,
+            None => MODEL::default(),
+        };
+
+        Ok(NodeSpecStruct { id: NODE::default(), name: Name::new( &object.metadata.name ), cpu_milli, memory_mib, num_gpu, gpu_milli: GPU::default(), model })
+    }
+}
+
+impl FromManifest for PodSpecStruct {
+    const KIND: &'static str = "Pod";
+
+    // Requests summed over containers. GPU share from annotations, GPU model from node selectors
+    fn from_manifest( object: &Object ) -> std::result::Result<Self, String> {
+        let spec = &object.spec;
+        let annotations = &object.metadata.annotations;
+
+        let mut cpu_milli = 0;
+        let mut memory_mib = 0;
+        let mut nvidia_gpu = 0;
+        for requests in spec.containers.iter().map(|c| &c.resources.requests) {
+            if let Some(cpu) = requests.get("cpu") { cpu_milli += cpu.cpu_milli()?; }
+            if let Some(mem) = requests.get("memory") { memory_mib += mem.memory_mib()?; }
+            if let Some(gpu) = requests.get(NVIDIA_GPU) { nvidia_gpu += gpu.count()?; }
+        }
+
+        let annotation = |name: &str| annotations.get(name).map(|value| Quantity(value.clone()).count()).transpose();
+
+        let (num_gpu, gpu_milli) = match (annotation(GPU_COUNT)?, annotation(GPU_MILLI_SHARE)?) {
+            (Some(0), _) => (0, 0),
+            (Some(count), milli) => (count as NUM, milli.unwrap_or(GPU_MILLI)),
+            (None, Some(milli)) => (1, milli),
+            (None, None) => (nvidia_gpu as NUM, if nvidia_gpu > 0 { GPU_MILLI } else { 0 }),
+        };
+        let gpu_memory_mib = annotations.get(GPU_MEMORY)
+            .map(|value| Quantity(value.clone()).memory_mib())
+            .transpose()?
+            .unwrap_or(0);
+
+        // Either a plain selector, or an "In" requirement listing every model the pod accepts
+        let mut models: Vec<&str> = spec.node_selector.get(GPU_MODEL_LABEL).map(String::as_str).into_iter().collect();
+        spec.affinity.node_affinity.required.node_selector_terms.iter()
+            .flat_map(|term| &term.match_expressions)
+            .filter(|req| req.key == GPU_MODEL_LABEL && req.operator == "In" )
+            .for_each(|req| models.extend( req.values.iter().map(String::as_str) ));
+        let model = parse_model( &models.join("|") )?;
+
+        let qos = match object.metadata.labels.get(QOS_LABEL) {
+            Some(qos) => Qos::deserialize( qos.as_str().into_deserializer() )
+                .map_err(|err: serde::de::value::Error| format!("invalid QoS class: {}", err))?,
+            None => Qos::default(),
+        };
+
+        Ok(PodSpecStruct {
+            id: POD::default(),
+            name: Name::new( &object.metadata.name ),
+            cpu_milli, memory_mib, num_gpu, gpu_milli, gpu_memory_mib, model, qos,
+            creation_time: TIME::default(),
+            deletion_time: TIME::default(),
+        })
+    }
+}
+
+// Read every object of the record's kind into the callback, numbered in file order.
+// Takes multi-document YAML and `kind: List` objects. Objects of other kinds are skipped
+pub fn process_yaml<T, R, F>(yaml_reader: R, mut callback: F ) -> Result<()>
+where
+    F: FnMut(usize, T) -> std::result::Result<(), String>,
+    R: std::io::Read,
+    T: FromManifest,
+{
+    let mut row = 0;
+
+    for document in serde_norway::Deserializer::from_reader(yaml_reader) {
+        let object = Object::deserialize(document).map_err(|err| Error::Manifest {
+            input: String::new(), row, name: String::new(), message: err.to_string(),
+        })?;
+
+        process_object( &object, &mut row, &mut callback )?;
+    }
+
+    Ok(())
+}
+
+fn process_object<T, F>( object: &Object, row: &mut usize, callback: &mut F ) -> Result<()>
+where
+    F: FnMut(usize, T) -> std::result::Result<(), String>,
+    T: FromManifest,
+{
+    object.items.iter().try_for_each(|item| process_object( item, row, callback ))?;
+
+    if object.kind != T::KIND { return Ok(()); }
+
+    let manifest_error = |message: String| Error::Manifest {
+        input: String::new(), row: *row, name: object.metadata.name.clone(), message,
+    };
+
+    let record = T::from_manifest( object ).map_err(manifest_error)?;
+    callback( *row, record ).map_err(manifest_error)?;
+
+    *row += 1;
+    Ok(())
+}
+
+
+impl NodeSpecStruct {
+    // Node manifest with the node's full capacity allocatable
+    pub fn to_manifest(&self) -> Object {
+        let mut capacity = BTreeMap::new();
+        capacity.insert( "cpu".to_string(), Quantity(format!("{}m", self.cpu_milli)) );
+        capacity.insert( "memory".to_string(), Quantity(format!("{}Mi", self.memory_mib)) );
+        capacity.insert( GPU_COUNT.to_string(), Quantity(self.num_gpu.to_string()) );
+
+        let mut labels = BTreeMap::new();
+        if !self.model.is_empty() {
+            labels.insert( GPU_MODEL_LABEL.to_string(), self.model.to_string() );
+        }
+
+        Object {
+            api_version: "v1".to_string(),
+            kind: Self::KIND.to_string(),
+            metadata: Metadata { name: self.name.to_string(), labels, ..Default::default() },
+            status: NodeStatus { allocatable: capacity.clone(), capacity },
+            ..Default::default()
+        }
+    }
+}
+
+impl PodSpecStruct {
+    // Pod manifest bound to `node_name`, on the GPUs with the given ids
+    pub fn to_manifest(&self, name: &str, node_name: &str, gpus: &[NUM] ) -> Object {
+        let mut requests = BTreeMap::new();
+        requests.insert( "cpu".to_string(), Quantity(format!("{}m", self.cpu_milli)) );
+        requests.insert( "memory".to_string(), Quantity(format!("{}Mi", self.memory_mib)) );
+
+        let mut annotations = BTreeMap::new();
+        if self.num_gpu > 0 {
+            annotations.insert( GPU_COUNT.to_string(), self.num_gpu.to_string() );
+            annotations.insert( GPU_MILLI_SHARE.to_string(), self.gpu_milli.to_string() );
+            if self.gpu_memory_mib > 0 {
+                annotations.insert( GPU_MEMORY.to_string(), format!("{}Mi", self.gpu_memory_mib) );
+            }
+        }
+        if !gpus.is_empty() {
+            let index: Vec<String> = gpus.iter().map(NUM::to_string).collect();
+            annotations.insert( GPU_INDEX.to_string(), index.join("-") );
+        }
+
+        let mut labels = BTreeMap::new();
+        labels.insert( QOS_LABEL.to_string(), self.qos.to_string() );
+
+        // Every model the pod accepts, as the node affinity the reader understands
+        let mut affinity = Affinity::default();
+        if !self.model.is_empty() {
+            let values = self.model.iter_names().map(|(name, _)| name.to_string()).collect();
+            affinity.node_affinity.required.node_selector_terms.push( NodeSelectorTerm {
+                match_expressions: vec![Requirement { key: GPU_MODEL_LABEL.to_string(), operator: "In".to_string(), values }],
+            });
+        }
+
+        Object {
+            api_version: "v1".to_string(),
+            kind: Self::KIND.to_string(),
+            metadata: Metadata { name: name.to_string(), labels, annotations },
+            spec: ObjectSpec {
+                node_name: node_name.to_string(),
+                containers: vec![Container {
+                    name: "main".to_string(),
+                    image: POD_IMAGE.to_string(),
+                    resources: Resources { requests },
+                }],
+                affinity,
+                ..Default::default()
+            },
+            ..Default::default()
+        }
+    }
+}
+
+// Write objects as multi-document YAML, which process_yaml reads back
+pub fn write_yaml( mut writer: impl std::io::Write, objects: &[Object] ) -> Result<()> {
+    objects.iter().try_for_each(|object| {
+        writeln!(writer, "---").map_err(Error::Write)?;
+        serde_norway::to_writer( &mut writer, object ).map_err(write_error)
+    })
+}
@@ -1,24 +1,95 @@
-use std::error::Error;
 use std::fmt::{Formatter};
+use std::io::{BufRead, BufReader, Read};
 use serde::{de, Deserialize, Deserializer};
 
+use crate::error::{Error, Result};
 use crate::types::*;
 
+pub mod manifest;
+pub use manifest::{process_yaml, FromManifest};
+
+#[allow(clippy::module_inception)]
 mod tests;
 
-pub fn process_csv<T, R, F>(csv_reader: R, mut callback: F ) -> Result<(), Box<dyn Error>>
+// Read records from either a CSV file or Kubernetes YAML manifests, whichever the input holds.
+// CSV headers have no colons, while the first line of a manifest is a key or a document marker.
+// Lines are read up to the first one that is neither blank nor a comment, then handed back to the reader
+pub fn process_input<T, R, F>(reader: R, callback: F ) -> Result<()>
 where
-    F: FnMut(usize, T) -> Result<(), Box<dyn Error>>,
+    F: FnMut(usize, T) -> std::result::Result<(), String>,
+    R: std::io::Read,
+    T: for<'de> Deserialize<'de> + FromManifest,
+{
+    let mut reader = BufReader::new(reader);
+    let mut head = Vec::new();
+
+    let is_manifest = loop {
+        let start = head.len();
+        if reader.read_until( b'\n', &mut head ).map_err(Error::Read)? == 0 { break false; }
+
+        let line = String::from_utf8_lossy( &head[start..] );
+        let line = line.trim();
+        if !line.is_empty() && !line.starts_with('#') {
+            break line.starts_with("---") || line.contains(':');
+        }
+    };
+
+    let reader = std::io::Cursor::new(head).chain(reader);
+
+    if is_manifest {
+        process_yaml( reader, callback )
+    } else {
+        process_csv( reader, callback )
+    }
+}
+
+// Read every row of the CSV into the callback, along with its row number.
+// Malformed rows, and rows the callback rejects, fail with their row, line and field
+pub fn process_csv<T, R, F>(csv_reader: R, mut callback: F ) -> Result<()>
+where
+    F: FnMut(usize, T) -> std::result::Result<(), String>,
     R: std::io::Read,
     T: for<'de> Deserialize<'de>,
 {
     let mut rdr = csv::Reader::from_reader(csv_reader);
+    let headers = rdr.headers().map_err(|err| csv_error( 0, 1, &err, None ))?.clone();
+
+    let mut record = csv::StringRecord::new();
+    let mut row = 0;
+
+    while rdr.read_record( &mut record ).map_err(|err| csv_error( row, 0, &err, Some(&headers) ))? {
+        let line = record.position().map_or(0, |pos| pos.line());
+
+        let value: T = record.deserialize( Some(&headers) )
+            .map_err(|err| csv_error( row, line, &err, Some(&headers) ))?;
+
+        callback( row, value ).map_err(|message| Error::Csv {
+            input: String::new(), row, line, field: None, message,
+        })?;
+
+        row += 1;
+    }
+
+    Ok(())
+}
+
+// Errors from a deserialized record carry no position of their own, so `line` stands in
+fn csv_error( row: usize, line: u64, err: &csv::Error, headers: Option<&csv::StringRecord> ) -> Error {
+    let line = err.position().map_or(line, |pos| pos.line());
 
-    rdr.deserialize().enumerate().try_for_each( |(i, result)| {
-        let record = result?;
+    let (field, message) = match err.kind() {
+        csv::ErrorKind::Deserialize { err, .. } => {
+            let field = err.field()
+                .and_then(|i| headers?.get(i as usize))
+                .map(|name| name.trim().to_string());
+            (field, err.kind().to_string())
+        },
+        csv::ErrorKind::UnequalLengths { expected_len, len, .. } =>
+            (None, format!("found {} fields, expected {}", len, expected_len)),
+        _ => (None, err.to_string()),
+    };
 
-        callback( i, record )
-    })
+    Error::Csv { input: String::new(), row, line, field, message }
 }
 
 
@@ -37,7 +108,7 @@ pub fn parse_gpu_spec<'de, D>( deserializer : D ) -> Result< MODEL, D::Error> wh
 
         fn visit_str<E>(self, v: &str) -> Result<Self::Value, E> where E: de::Error {
             bitflags::parser::from_str(v).map_err(|err|
-                E::custom(format!("Error: {}, invalid gpu_spec: {}", err.to_string(), v))
+                E::custom(format!("{}, invalid gpu_spec: {}", err, v))
             )
         }
     }
@@ -3,6 +3,7 @@ mod tests {
     use rstest::rstest;
 
     use crate::csv_reader::*;
+    use crate::error::Error;
 
     #[rstest]
     #[case("sn,cpu_milli,memory_mib,gpu,model
@@ -52,4 +53,215 @@ mod tests {
             Ok(())
         }).unwrap();
     }
-}
\ No newline at end of file
+
+    #[rstest]
+    #[case::bad_number("name,cpu_milli,memory_mib,num_gpu,gpu_milli
+        openb-pod-0095,4152,10600,1,810
+        openb-pod-0096,18708,64512,1,lots", 1, 3, Some("gpu_milli"), "invalid digit")]
+    // The csv crate only knows the field of errors it raises itself, so custom parsers name theirs
+    #[case::bad_gpu_spec("name,cpu_milli,memory_mib,num_gpu,gpu_milli,gpu_spec
+        openb-pod-0095,4152,10600,1,810,V100M99", 0, 2, None, "invalid gpu_spec: V100M99")]
+    #[case::missing_field("name,cpu_milli,memory_mib,num_gpu,gpu_milli
+        openb-pod-0095,4152,10600,1,810
+        openb-pod-0096,18708,64512,1,1000
+        openb-pod-0097,8000,30517,1", 2, 4, None, "found 4 fields, expected 5")]
+    fn read_malformed_csv( #[case] pod_csv: &str, #[case] row: usize, #[case] line: u64,
+                           #[case] field: Option<&str>, #[case] message: &str ) {
+
+        let err = process_csv(pod_csv.as_bytes(), |_, _: PodSpecStruct| Ok(())).unwrap_err();
+
+        let Error::Csv { row: err_row, line: err_line, field: err_field, message: err_message, .. } = &err else {
+            panic!("expected a CSV error, got {}", err);
+        };
+        assert_eq!((*err_row, *err_line, err_field.as_deref()), (row, line, field), "{}", err);
+        assert!(err_message.contains( message ), "{}", err);
+    }
+
+    #[rstest]
+    fn read_rejected_row() {
+        let node_csv = "sn,cpu_milli,memory_mib,gpu,model
+            openb-node-0021,64000,262144,2,P100
+            openb-node-0022,128000,786432,9,G3";
+
+        let err = process_csv(node_csv.as_bytes(), |_, record: NodeSpecStruct| {
+            if record.num_gpu > 8 { Err(format!("{} GPUs, at most 8", record.num_gpu)) } else { Ok(()) }
+        }).unwrap_err().in_input("cluster");
+
+        assert_eq!(err.to_string(), "cluster row 1 (line 3): 9 GPUs, at most 8");
+    }
+
+    const NODE_YAML: &str = "
+apiVersion: v1
+kind: Node
+metadata:
+  name: openb-node-0021
+  labels:
+    alibabacloud.com/gpu-card-model: P100
+status:
+  capacity:
+    cpu: 64
+    memory: 256Gi
+    alibabacloud.com/gpu-count: \"2\"
+---
+apiVersion: v1
+kind: List
+items:
+- apiVersion: v1
+  kind: Node
+  metadata:
+    name: openb-node-1480
+  status:
+    capacity:
+      cpu: \"96\"
+      memory: 524288Mi
+    allocatable:
+      cpu: 96000m
+- apiVersion: v1
+  kind: Service
+  metadata:
+    name: ignored
+";
+
+    const POD_YAML: &str = "
+apiVersion: v1
+kind: Pod
+metadata:
+  name: openb-pod-0095
+  labels:
+    alibabacloud.com/qos: BE
+  annotations:
+    alibabacloud.com/gpu-milli: \"810\"
+    alibabacloud.com/gpu-count: \"1\"
+    alibabacloud.com/gpu-mem: 6Gi
+spec:
+  containers:
+  - name: main
+    resources:
+      requests:
+        cpu: 4152m
+        memory: 10600Mi
+---
+apiVersion: v1
+kind: Pod
+metadata:
+  name: openb-pod-7854
+  labels:
+    alibabacloud.com/qos: LS
+  annotations:
+    alibabacloud.com/gpu-count: \"1\"
+spec:
+  containers:
+  - resources:
+      requests:
+        cpu: \"11.3\"
+        memory: 48Gi
+  affinity:
+    nodeAffinity:
+      requiredDuringSchedulingIgnoredDuringExecution:
+        nodeSelectorTerms:
+        - matchExpressions:
+          - key: alibabacloud.com/gpu-card-model
+            operator: In
+            values: [P100, V100M16, V100M32]
+---
+apiVersion: v1
+kind: Pod
+metadata:
+  name: openb-pod-0017
+spec:
+  nodeSelector:
+    alibabacloud.com/gpu-card-model: G3
+  containers:
+  - resources:
+      requests:
+        cpu: 88
+        memory: 320Gi
+        nvidia.com/gpu: 8
+";
+
+    #[rstest]
+    fn read_node_manifests() {
+        let csv = "sn,cpu_milli,memory_mib,gpu,model
+        openb-node-0021,64000,262144,2,P100
+        openb-node-1480,96000,524288,0,";
+
+        let read = |input: &str| {
+            let mut records = Vec::new();
+            process_input(input.as_bytes(), |_, record: NodeSpecStruct| { records.push(record); Ok(()) }).unwrap();
+            records
+        };
+
+        assert_eq!(read( NODE_YAML ), read( csv ));
+
+        // However long the comments before the first object
+        let commented = "# generated\n".repeat(2000) + NODE_YAML;
+        assert_eq!(read( &commented ), read( csv ));
+    }
+
+    #[rstest]
+    #[case("1e2", "1Pi", 100000, 1 << 30)]
+    #[case("0.5k", "2E", 500000, 1907348632813)]
+    #[case("2500m", "1.5e+9", 2500, 1431)]
+    fn read_quantities( #[case] cpu: &str, #[case] memory: &str, #[case] cpu_milli: CPU, #[case] memory_mib: MEM ) {
+        let yaml = format!("kind: Node
+status:
+  capacity: {{cpu: \"{}\", memory: \"{}\"}}", cpu, memory);
+
+        process_input(yaml.as_bytes(), |_, node: NodeSpecStruct| {
+            assert_eq!((node.cpu_milli, node.memory_mib), (cpu_milli, memory_mib));
+            Ok(())
+        }).unwrap();
+    }
+
+    #[rstest]
+    fn read_pod_manifests() {
+        let csv = "name,cpu_milli,memory_mib,num_gpu,gpu_milli,gpu_memory_mib,gpu_spec,qos
+        openb-pod-0095,4152,10600,1,810,6144,,BE
+        openb-pod-7854,11300,49152,1,1000,0,P100|V100M16|V100M32,LS
+        openb-pod-0017,88000,327680,8,1000,0,G3,Burstable";
+
+        let read = |input: &str| {
+            let mut records = Vec::new();
+            process_input(input.as_bytes(), |_, record: PodSpecStruct| { records.push(record); Ok(()) }).unwrap();
+            records
+        };
+
+        assert_eq!(read( POD_YAML ), read( csv ));
+    }
+
+    #[rstest]
+    #[case::bad_quantity("kind: Pod
+metadata:
+  name: openb-pod-0001
+spec:
+  containers:
+  - resources:
+      requests:
+        cpu: lots", "openb-pod-0001", "invalid quantity: lots")]
+    #[case::bad_suffix("kind: Node
+metadata:
+  name: openb-node-0003
+status:
+  capacity: {cpu: 8, memory: 8Zi}", "openb-node-0003", "unsupported quantity suffix: 8Zi")]
+    #[case::bad_model("kind: Node
+metadata:
+  name: openb-node-0001
+  labels:
+    alibabacloud.com/gpu-card-model: V100M99
+status:
+  capacity: {cpu: 8, memory: 8Gi}", "openb-node-0001", "invalid GPU model: V100M99")]
+    #[case::no_capacity("kind: Node
+metadata:
+  name: openb-node-0002", "openb-node-0002", "no cpu capacity")]
+    fn read_malformed_manifest( #[case] yaml: &str, #[case] name: &str, #[case] message: &str ) {
+        let err = process_input(yaml.as_bytes(), |_, _: NodeSpecStruct| Ok(()))
+            .and_then(|_| process_input(yaml.as_bytes(), |_, _: PodSpecStruct| Ok(())))
+            .unwrap_err();
+
+        let Error::Manifest { name: err_name, message: err_message, .. } = &err else {
+            panic!("expected a manifest error, got {}", err);
+        };
+        assert_eq!(err_name, name);
+        assert!(err_message.contains( message ), "{}", err);
+    }
+}
@@ -0,0 +1,128 @@
+use std::path::PathBuf;
+use crate::evaluator::validate::Violation;
+
+// Errors reading the simulator's inputs, and running it on them
+#[derive(Debug)]
+pub enum Error {
+    // Input file could not be opened
+    Open { path: PathBuf, source: std::io::Error },
+
+    // Malformed CSV row. `row` counts data rows from 0, which is also the pod or node id,
+    // `line` is where the row starts in the file, header included
+    Csv { input: String, row: usize, line: u64, field: Option<String>, message: String },
+
+    // Input stream could not be read
+    Read(std::io::Error),
+
+    // Output file could not be created, or written to
+    Create { path: PathBuf, source: std::io::Error },
+    Write(std::io::Error),
+
+    // Kubernetes manifest that is malformed, or that the simulator cannot model.
+    // `row` counts the objects of the expected kind, as for CSV rows
+    Manifest { input: String, row: usize, name: String, message: String },
+
+    // Input without a single row
+    Empty { input: String },
+
+    // Trace replay of an input without creation_time and deletion_time columns
+    NoTimeline { input: String },
+
+    // Strict mode caught a scheduler breaking the cluster's invariants
+    Violation(Violation),
+
+    // Scheduler or decider spec that does not parse
+    Spec(String),
+}
+
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+impl Error {
+    // Name the input a CSV error came from, e.g. the workload or cluster reading it
+    pub fn in_input(self, name: &str ) -> Self {
+        match self {
+            Error::Csv { row, line, field, message, .. } =>
+                Error::Csv { input: name.to_string(), row, line, field, message },
+            Error::Manifest { row, name: object, message, .. } =>
+                Error::Manifest { input: name.to_string(), row, name: object, message },
+            err => err,
+        }
+    }
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::Open { path, source } => write!(f, "cannot open {}: {}", path.display(), source ),
+            Error::Csv { input, row, line, field, message } => {
+                if !input.is_empty() {
+                    write!(f, "{} ", input )?;
+                }
+                write!(f, "row {} (line {})", row, line )?;
+                if let Some(field) = field {
+                    write!(f, ", field {}", field )?;
+                }
+                write!(f, ": {}", message )
+            },
+            Error::Read(source) => write!(f, "cannot read input: {}", source ),
+            Error::Create { path, source } => write!(f, "cannot create {}: {}", path.display(), source ),
+            Error::Write(source) => write!(f, "cannot write output: {}", source ),
+            Error::Manifest { input, row, name, message } => {
+                if !input.is_empty() {
+                    write!(f, "{} ", input )?;
+                }
+                write!(f, "object {}", row )?;
+                if !name.is_empty() {
+                    write!(f, " ({})", name )?;
+                }
+                write!(f, ": {}", message )
+            },
+            Error::Empty { input } => write!(f, "{} has no rows", input ),
+            Error::NoTimeline { input } => write!(f, "{} has no creation_time and deletion_time to replay", input ),
+            Error::Violation(violation) => write!(f, "{}", violation ),
+            Error::Spec(message) => write!(f, "{}", message ),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Open { source, .. } => Some(source),
+            Error::Read(source) => Some(source),
+            Error::Create { source, .. } => Some(source),
+            Error::Write(source) => Some(source),
+            _ => None,
+        }
+    }
+}
+
+impl From<Violation> for Error {
+    fn from(violation: Violation) -> Self { Error::Violation(violation) }
+}
+
+// Open an input file, keeping its path for the error
+pub fn open( path: impl Into<PathBuf> ) -> Result<std::fs::File> {
+    let path = path.into();
+
+    std::fs::File::open( &path ).map_err(|source| Error::Open { path, source })
+}
+
+// Create an output file, keeping its path for the error
+pub fn create( path: impl Into<PathBuf> ) -> Result<std::fs::File> {
+    let path = path.into();
+
+    std::fs::File::create( &path ).map_err(|source| Error::Create { path, source })
+}
+
+// Serializers failing to write the output, e.g. CSV or JSON
+pub fn write_error( err: impl Into<Box<dyn std::error::Error + Send + Sync>> ) -> Error {
+    Error::Write( std::io::Error::other(err) )
+}
+
+// Create an output directory and its parents, keeping its path for the error
+pub fn create_dir( path: impl Into<PathBuf> ) -> Result<()> {
+    let path = path.into();
+
+    std::fs::create_dir_all( &path ).map_err(|source| Error::Create { path, source })
+}
@@ -1,19 +1,125 @@
 use crate::evaluator::workload::*;
 use crate::evaluator::*;
-use rand::prelude::ThreadRng;
+use crate::mig::{Slices, Slot};
+use rand::rngs::StdRng;
+use rand::SeedableRng;
 use std::cell::RefCell;
-use std::collections::HashMap;
+use std::cmp::Ordering;
+use std::collections::{BinaryHeap, HashMap};
 use std::io::Read;
 use std::rc::Rc;
 
-type FragDelta = HashMap<PodSpecKey, Vec<GPU>>;
+type FragDelta = HashMap<PodSpecKey, Vec<Option<FRAG>>>;
+
+// One placement of a bound task. Sampled workloads can bind the same task more than once,
+// so `bind` numbers the binds of the batch to tell them apart
+#[derive(Debug, Clone)]
+#[public]
+struct Binding {
+    bind: usize,
+    task: PodSpec,
+    pick: SchedulingPick,
+
+    // MIG instance, when the task holds a share of a MIG GPU
+    slot: Option<Slot>,
+
+    // GPU memory held on each of its GPUs: what the task asks for, or all of its MIG instance's
+    gpu_memory_mib: MEM,
+
+    // Clock at bind time
+    time: TIME,
+}
+
+impl Binding {
+    // What the task holds of each of its GPUs: its share, or all of its MIG instance
+    pub fn gpu_milli(&self) -> GPU {
+        self.slot.map_or(self.task.per_gpu_milli(), |slot| slot.gpu_milli())
+    }
+
+    // MIG slices of one of its GPUs with the task on it. Only multi-GPU tasks go without a slot,
+    // and they hold their GPUs whole
+    pub fn claimed(&self, slices: Slices ) -> Slices {
+        match self.slot {
+            Some(slot) => slices.claim( slot ),
+            None => {
+                debug_assert!(!self.task.single_gpu(), "pod {} holds a share of a MIG GPU without an instance", self.task.name);
+                Slices::FULL
+            },
+        }
+    }
+
+    // Reverse of claimed
+    pub fn released(&self, slices: Slices ) -> Slices {
+        match self.slot {
+            Some(slot) => slices.release( slot ),
+            None => {
+                debug_assert!(!self.task.single_gpu(), "pod {} holds a share of a MIG GPU without an instance", self.task.name);
+                Slices::default()
+            },
+        }
+    }
+
+    pub fn placement(&self) -> Placement {
+        let (node_ref, gpu_vec) = &self.pick;
+
+        Placement {
+            pod: self.task.id,
+            pod_name: self.task.name.clone(),
+            bind: self.bind,
+            node: node_ref.borrow().spec.id,
+            node_name: node_ref.borrow().spec.name.clone(),
+            gpus: gpu_vec.iter().map(|gpu| gpu.borrow().id ).collect(),
+            gpu_milli: self.gpu_milli(),
+            gpu_memory_mib: self.gpu_memory_mib,
+            slot: self.slot,
+        }
+    }
+}
+
+// Where each bound task was placed
+type Bindings = HashMap<POD, Vec<Binding>>;
+
+// Bound task that releases its resources at `time`, unless that binding was already undone
+#[derive(Debug, Clone)]
+#[public]
+struct Departure {
+    time: TIME,
+    task: PodSpec,
+    bind: usize,
+}
+
+// Earliest departure on top of the (max-)heap
+impl Ord for Departure {
+    fn cmp(&self, other: &Self) -> Ordering {
+        other.time.cmp(&self.time)
+            .then_with(|| other.task.id.cmp(&self.task.id))
+    }
+}
+
+impl PartialOrd for Departure {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> { Some(self.cmp(other)) }
+}
+
+impl PartialEq for Departure {
+    fn eq(&self, other: &Self) -> bool { self.cmp(other) == Ordering::Equal }
+}
+
+impl Eq for Departure {}
+
+// Share of a GPU once a task of `size` joins it: the size while all its tasks agree, GPU::MAX once they don't
+fn mixed_share( share: GPU, size: GPU ) -> GPU {
+    if share == 0 || share == size { size } else { GPU::MAX }
+}
+
+// Node resources that fully determine an empty node's fragmentation
+type EmptyNodeKey = (CPU, MEM, NUM, MODEL);
 
 #[derive(Debug, Clone)]
 #[public]
 struct ClusterStruct {
     name: String,
 
-    rng: RefCell<ThreadRng>,
+    rng: RefCell<StdRng>,
 
     workload: Workload,
 
@@ -22,9 +128,22 @@ struct ClusterStruct {
     nodes: Vec<NodeInfo>,
 
     // Key Optimization:
-    // We keep a precomputed vector of fragmentation deltas for each task per node,
-    // and only update when binding a task to a node.
-    frag_delta: FragDelta,
+    // We keep a precomputed vector of fragmentation deltas for each task per node.
+    // Binding a task to a node only marks that node's entries stale (None), and they are
+    // recomputed when looked up, so schedulers that never read the cache do not pay for it.
+    // Indexed by node id. Nodes the task cannot be placed on hold FRAG::MAX.
+    frag_delta: RefCell<FragDelta>,
+
+    // Placement of every bound task, so it can be released later.
+    // Nodes list the placements of their own tasks, to find what to evict from a node
+    bindings: RefCell<Bindings>,
+
+    // Trace replay: bound tasks ordered by departure time
+    departures: RefCell<BinaryHeap<Departure>>,
+
+    // Binds so far this batch, and the count at each node's latest bind (0 if none), for least-recently-used ordering
+    binds: RefCell<usize>,
+    last_bound: RefCell<Vec<usize>>,
 
     metrics: RefCell<NodeMetrics>,
 
@@ -34,6 +153,7 @@ pub type Cluster = ClusterStruct;
 
 #[derive(Debug, Clone)]
 #[derive(Default)]
+#[derive(serde::Serialize)]
 #[public]
 struct NodeMetrics {
     gpu_total: GPU,
@@ -44,22 +164,34 @@ struct NodeMetrics {
     alloc_rate: f64,    // 1 - gpu_unallocated / gpu_total
 }
 
+impl NodeMetrics {
+    fn update_rates(&mut self) {
+        self.alloc_rate = 1f64 - (self.gpu_unallocated as f64 / self.gpu_total as f64);
+        self.frag_rate = if self.gpu_unallocated == 0 { 0f64 } else {
+            self.frag_total as f64 / self.gpu_unallocated as f64
+        };
+    }
+}
+
 impl ClusterStruct {
 
-    fn read_node_specs( node_reader: impl  Read ) -> Vec<NodeSpec> {
+    fn read_node_specs( node_reader: impl  Read ) -> Result<Vec<NodeSpec>> {
         let mut specs = Vec::new();
 
-        process_csv( node_reader, | i, mut record: NodeSpecStruct | {
+        process_input( node_reader, | i, mut record: NodeSpecStruct | {
             record.id = i;
+            if record.name.is_empty() {
+                record.name = Name::new( &format!("node-{}", i) );
+            }
             record.gpu_milli = record.num_gpu as GPU * GPU_MILLI;
             let spec = Rc::new( record );
 
             specs.push( spec);
             Ok(())
 
-        }).expect("Failed to process Cluster CSV");
+        })?;
 
-        specs
+        Ok(specs)
     }
 
     fn reset_cluster(&mut self) {
@@ -68,6 +200,14 @@ impl ClusterStruct {
 
         let mut nodes = Vec::with_capacity(self.num_nodes);
 
+        let keys: Vec<&PodSpecKey> = self.workload.task_count.keys().collect();
+        let mut frag_delta: FragDelta = keys.iter()
+            .map(|&key| (key.clone(), vec![None; self.num_nodes]))
+            .collect();
+
+        // Empty nodes with the same specs fragment identically, so only compute once per spec
+        let mut empty_frag: HashMap<EmptyNodeKey, (GPU, Vec<FRAG>)> = HashMap::new();
+
         for spec in &self.specs {
 
             metrics.gpu_unallocated += spec.gpu_milli;
@@ -84,32 +224,67 @@ impl ClusterStruct {
 
                 gpu_unallocated: spec.gpu_milli,
                 gpu_frag: 0,
+
+                pods: Vec::new(),
             };
 
             node.gpu_rem = (0..spec.num_gpu).map(|id| {
-                Rc::new(RefCell::new(GpuInfoStruct { id, gpu_milli: GPU_MILLI } ))
+                let mig = spec.model.mig().then(Slices::default);
+                let gpu_memory_mib = spec.model.gpu_memory_mib();
+                Rc::new(RefCell::new(GpuInfoStruct { id, gpu_milli: GPU_MILLI, share: 0, mig, gpu_memory_mib, gpu_memory_total: gpu_memory_mib } ))
             }).collect();
 
-            nodes.push( Rc::new(RefCell::new(node)) );
+            let spec_key = (spec.cpu_milli, spec.memory_mib, spec.num_gpu, spec.model.clone());
+            let (gpu_frag, deltas) = empty_frag.entry(spec_key).or_insert_with(|| {
+                let state = node.frag_state();
+                let gpu_frag = state.node_frag( &self.workload );
+
+                let deltas = keys.iter()
+                    .map(|&key| state.frag_delta( key, gpu_frag, &self.workload ))
+                    .collect();
+
+                (gpu_frag, deltas)
+            });
 
-            // TODO: calculate frag_delta
+            node.gpu_frag = *gpu_frag;
+            metrics.frag_total += node.gpu_frag;
+
+            keys.iter().zip(deltas.iter()).for_each(|(&key, &delta)| {
+                frag_delta.get_mut(key).unwrap()[spec.id] = Some(delta);
+            });
+
+            nodes.push( Rc::new(RefCell::new(node)) );
         }
 
+        metrics.update_rates();
+
         self.nodes = nodes;
+        *self.frag_delta.borrow_mut() = frag_delta;
+        self.bindings.borrow_mut().clear();
+        self.departures.borrow_mut().clear();
+        *self.binds.borrow_mut() = 0;
+        *self.last_bound.borrow_mut() = vec![0; self.num_nodes];
     }
 
 
-    pub fn new( name: String,  node_csv : impl Read, workload: Workload ) -> Self {
+    pub fn new( name: String, seed: SEED, node_csv : impl Read, workload: Workload ) -> Result<Self> {
 
-        let specs = Self::read_node_specs( node_csv );
+        let specs = Self::read_node_specs( node_csv ).map_err(|err| err.in_input( &name ))?;
+        if specs.is_empty() {
+            return Err(Error::Empty { input: name });
+        }
         let num_nodes = specs.len();
 
         let nodes = Default::default();
 
         let metrics = NodeMetrics::default();
-        let frag_delta = Default::default();
+        let frag_delta = RefCell::new(FragDelta::default());
+        let bindings = RefCell::new(Bindings::new());
+        let departures = RefCell::new(BinaryHeap::new());
+        let binds = RefCell::new(0);
+        let last_bound = RefCell::new(vec![0; num_nodes]);
 
-        let rng = RefCell::new(rand::rng());
+        let rng = RefCell::new(StdRng::seed_from_u64(seed));
         let metrics = RefCell::new(metrics);
 
         let mut cluster = Self {
@@ -118,71 +293,294 @@ impl ClusterStruct {
             workload,
             specs, nodes, num_nodes,
             frag_delta,
+            bindings,
+            departures,
+            binds, last_bound,
             metrics,
         };
 
         cluster.reset_cluster();
-        cluster
+        Ok(cluster)
     }
 
     // Basic filtering pass. Checks availability of resources, and model specs if provided
     pub fn filter_nodes( &self, task: PodSpec ) -> impl Iterator<Item=NodeInfo>  {
 
         self.nodes.iter()
+            .filter( move | node | node.borrow().fits( &task ) )
             .cloned()
-            .filter( move | node | {
-            let node = node.borrow();
-            let scalar_resources: bool =
-                task.cpu_milli <= node.cpu_rem &&
-                task.memory_mib <= node.mem_rem;
-
-            let gpu_resources: bool =
-                task.gpu_milli <= node.gpu_part ||
-                task.num_gpu <= node.gpu_full;
+    }
 
-            let model_match: bool =
-                task.model.is_empty() ||
-                task.model.intersects( node.spec.model.clone() );
+    // Returns the bind number, which identifies this binding of the task
+    pub fn bind_task(&self, task: PodSpec, (node_ref, gpu_vec): SchedulingPick ) -> usize {
+        let bind = {
+            let mut binds = self.binds.borrow_mut();
+            *binds += 1;
+            *binds
+        };
 
-            scalar_resources && gpu_resources && model_match
-        })
-    }
+        // A share of a MIG GPU is handed out as the smallest instance that covers it and its memory,
+        // and the task holds all of the instance's memory
+        let slot = match task.single_gpu() {
+            true => gpu_vec[0].borrow().slot( &task ),
+            false => None,
+        };
+        // Picks are checked for a free instance in strict mode, so this is a scheduler bug
+        assert!(slot.is_some() || !task.single_gpu() || gpu_vec[0].borrow().mig.is_none(),
+                "pod {} picked a MIG GPU without a free instance for its share and memory", task.name);
+        let gpu_memory_mib = slot.zip( gpu_vec.first().and_then(|gpu| gpu.borrow().gpu_memory_total) )
+            .map_or(task.gpu_memory_mib, |(slot, capacity)| slot.gpu_memory_mib( capacity ));
+
+        let binding = Binding {
+            bind,
+            task: task.clone(),
+            pick: (node_ref.clone(), gpu_vec.clone()),
+            slot,
+            gpu_memory_mib,
+            time: self.workload.now(),
+        };
+        let gpu_milli = binding.gpu_milli();
 
-    pub fn bind_task(&self, task: PodSpec, (node_ref, gpu_vec): SchedulingPick ) {
+        // The placement reads the node, so take it before the node is borrowed for the update
+        let placement = binding.placement();
         let mut node =  node_ref.borrow_mut();
 
+        self.last_bound.borrow_mut()[node.spec.id] = bind;
+        node.pods.push( placement );
+
         node.cpu_rem -= task.cpu_milli;
         node.mem_rem -= task.memory_mib;
 
         if task.single_gpu() {
-            gpu_vec[0].borrow_mut().gpu_milli -= task.gpu_milli;
+            let mut gpu = gpu_vec[0].borrow_mut();
+            gpu.gpu_milli -= gpu_milli;
+            gpu.share = mixed_share( gpu.share, task.gpu_milli );
 
         } else {
-            gpu_vec.into_iter().for_each(|gpu | {
-                gpu.borrow_mut().gpu_milli = 0;
+            gpu_vec.iter().for_each(|gpu | {
+                let mut gpu = gpu.borrow_mut();
+                gpu.gpu_milli = 0;
+                gpu.share = GPU_MILLI;
             })
         }
 
+        gpu_vec.iter().for_each(|gpu| {
+            let mut gpu = gpu.borrow_mut();
+            gpu.mig = gpu.mig.map(|slices| binding.claimed( slices ));
+            gpu.gpu_memory_mib = gpu.gpu_memory_mib.map(|gpu_memory_mib| gpu_memory_mib - binding.gpu_memory_mib );
+        });
+
+        self.bindings.borrow_mut()
+            .entry(task.id)
+            .or_default()
+            .push( binding );
+
+        let gpu_total = gpu_milli * gpu_vec.len() as GPU;
+        node.gpu_unallocated -= gpu_total;
+        self.metrics.borrow_mut().gpu_unallocated -= gpu_total;
+
+        self.update_node( &mut node );
+
+        bind
+    }
+
+    // Reverse of bind_task, for one particular binding of the task. Returns its resources to the node and GPUs recorded at bind time.
+    // Returns the released binding, or None if it was already undone.
+    pub fn unbind(&self, task_id: POD, bind: usize ) -> Option<Binding> {
+        let binding = {
+            let mut bindings = self.bindings.borrow_mut();
+            let task_bindings = bindings.get_mut(&task_id)?;
+
+            let i = task_bindings.iter().position(|b| b.bind == bind )?;
+            let binding = task_bindings.remove(i);
+            if task_bindings.is_empty() { bindings.remove(&task_id); }
+            binding
+        };
+
+        let task = &binding.task;
+        let (node_ref, gpu_vec) = &binding.pick;
+        let mut node =  node_ref.borrow_mut();
+
+        node.pods.retain(|placement| placement.bind != bind );
+
+        node.cpu_rem += task.cpu_milli;
+        node.mem_rem += task.memory_mib;
+
+        // The GPUs' share is what the tasks left on them would have given it
+        let residents = self.residents( &node );
+
+        let gpu_milli = binding.gpu_milli();
+        gpu_vec.iter().for_each(|gpu_ref| {
+            let mut gpu = gpu_ref.borrow_mut();
+            gpu.gpu_milli += gpu_milli;
+            gpu.mig = gpu.mig.map(|slices| binding.released( slices ));
+            gpu.gpu_memory_mib = gpu.gpu_memory_mib.map(|gpu_memory_mib| gpu_memory_mib + binding.gpu_memory_mib );
+
+            gpu.share = residents.iter()
+                .filter(|resident| resident.pick.1.iter().any(|other| Rc::ptr_eq( other, gpu_ref )) )
+                .map(|resident| if resident.task.single_gpu() { resident.task.gpu_milli } else { GPU_MILLI })
+                .fold(0, mixed_share);
+        });
+
+        let gpu_total = gpu_milli * gpu_vec.len() as GPU;
+        node.gpu_unallocated += gpu_total;
+        self.metrics.borrow_mut().gpu_unallocated += gpu_total;
+
+        self.update_node( &mut node );
+        drop(node);
+
+        Some(binding)
+    }
+
+    // Tasks currently bound to the node
+    pub fn residents(&self, node: &NodeInfoStruct ) -> Vec<Binding> {
+        let bindings = self.bindings.borrow();
+
+        node.pods.iter()
+            .filter_map(|placement| {
+                bindings.get(&placement.pod)?.iter().find(|b| b.bind == placement.bind ).cloned()
+            })
+            .collect()
+    }
+
+    // Where the task is bound, by its latest binding. None if it is not bound
+    pub fn placement(&self, task_id: POD ) -> Option<Placement> {
+        Some(self.bindings.borrow().get(&task_id)?.last()?.placement())
+    }
+
+    // Every bound task, by node
+    pub fn placements(&self) -> Vec<Placement> {
+        self.nodes.iter()
+            .flat_map(|node| node.borrow().pods.clone())
+            .collect()
+    }
+
+    // Preemption: of the nodes `admits` accepts, the one where evicting the fewest preemptible tasks makes room
+    // for the task, the bindings to evict, and the GPUs to place the task on once they are gone.
+    // Prefers evicting the most recently bound tasks, which lose the least work.
+    pub fn preemption_victims(&self, task: &PodSpecStruct, admits: impl Fn(&NodeInfoStruct) -> bool ) -> Option<(NodeInfo, Vec<Binding>, Vec<NUM>)> {
+        self.nodes.iter()
+            .filter_map(|node_ref| {
+                let node = node_ref.borrow();
+                if !admits( &node ) { return None; }
+
+                let mut candidates: Vec<Binding> = self.residents(&node).into_iter()
+                    .filter(|b| b.task.qos.preemptible() )
+                    .collect();
+                candidates.sort_by_key(|b| std::cmp::Reverse((b.time, b.bind)) );
+
+                // Evict newest first until the task fits
+                let mut state = node.frag_state();
+                let mut victims = Vec::new();
+
+                for victim in candidates {
+                    if state.place( task ).is_some() { break; }
+
+                    state.release( &victim );
+                    victims.push( victim );
+                }
+
+                // Nothing to evict, or not enough
+                if victims.is_empty() || state.place( task ).is_none() { return None; }
+
+                // Keep only the evictions the task still needs, oldest first
+                for i in (0..victims.len()).rev() {
+                    let mut without = state.clone();
+                    without.claim( &victims[i] );
+
+                    if without.place( task ).is_some() {
+                        state = without;
+                        victims.remove(i);
+                    }
+                }
+
+                let gpus = state.place( task )?;
+                Some((node_ref.clone(), victims, gpus))
+            })
+            .min_by_key(|(_, victims, _)| victims.len() )
+    }
+
+    // When a task was last bound to the node, counted in binds this batch. 0 if it has not been used yet
+    pub fn last_bound(&self, node: &NodeInfoStruct ) -> usize {
+        self.last_bound.borrow()[node.spec.id]
+    }
+
+    #[allow(unused)]
+    pub fn is_bound(&self, task: &PodSpecStruct ) -> bool {
+        self.bindings.borrow().contains_key(&task.id)
+    }
+
+    // Recompute node aggregates after its GPUs changed
+    fn update_node(&self, node: &mut NodeInfoStruct ) {
         node.gpu_full = node.gpu_rem.iter()
             .filter(|gpu| { gpu.borrow().gpu_milli == GPU_MILLI })
             .count();
 
         node.gpu_part = node.gpu_rem.iter()
             .filter(|gpu| { gpu.borrow().gpu_milli < GPU_MILLI })
-            .map( |gpu| gpu.borrow().gpu_milli )
+            .map( |gpu| gpu.borrow().usable() )
             .max()
             .unwrap_or(0);
 
-        node.gpu_unallocated -= task.gpu_milli;
+        // Only this node changed, so only its fragmentation needs recomputing, and its deltas on the next lookup
+        let gpu_frag = node.frag_state().node_frag( &self.workload );
 
-        // TODO: GPU frag and GPU frag delta
+        self.frag_delta.borrow_mut().values_mut().for_each(|deltas| deltas[node.spec.id] = None );
 
 
         // Cluster metrics
         let mut metrics = self.metrics.borrow_mut();
 
-        metrics.gpu_unallocated -= task.gpu_milli;
-        metrics.alloc_rate = 1f64 - (metrics.gpu_unallocated as f64 / metrics.gpu_total as f64);
+        metrics.frag_total -= node.gpu_frag;
+        metrics.frag_total += gpu_frag;
+        node.gpu_frag = gpu_frag;
+
+        metrics.update_rates();
+    }
+
+    // Trace replay: release the task's resources once the clock reaches `time`.
+    // `bind` is the number bind_task returned, so a binding undone by preemption is not released twice
+    pub fn depart_at(&self, time: TIME, task: PodSpec, bind: usize ) {
+        self.departures.borrow_mut().push( Departure { time, task, bind } );
+    }
+
+    // When the next task departs, if any is still running
+    pub fn next_departure(&self) -> Option<TIME> {
+        self.departures.borrow().peek().map(|departure| departure.time)
+    }
+
+    // Unbind every task that departs at or before `time`. Returns how many were released
+    pub fn release_until(&self, time: TIME ) -> usize {
+        let mut released = 0;
+
+        loop {
+            let departure = {
+                let mut departures = self.departures.borrow_mut();
+                match departures.peek() {
+                    Some(next) if next.time <= time => departures.pop().unwrap(),
+                    _ => break,
+                }
+            };
+
+            if self.unbind( departure.task.id, departure.bind ).is_some() {
+                released += 1;
+            }
+        }
+
+        released
+    }
+
+    // How much the node's fragmentation would grow if the task were placed on it.
+    // O(1) for task types in the workload once cached since the node's last bind, FRAG::MAX if the task does not fit.
+    pub fn frag_delta(&self, task: &PodSpecStruct, node: &NodeInfoStruct ) -> FRAG {
+        let key = task.key();
+        let mut frag_delta = self.frag_delta.borrow_mut();
+
+        match frag_delta.get_mut(&key).map(|deltas| &mut deltas[node.spec.id]) {
+            Some(Some(delta)) => *delta,
+            Some(stale) => *stale.insert( node.frag_state().frag_delta( &key, node.gpu_frag, &self.workload ) ),
+            None => node.frag_state().frag_delta( &key, node.gpu_frag, &self.workload ),
+        }
     }
 
     pub fn deploy(&mut self) -> NodeMetrics {
@@ -194,14 +592,245 @@ impl ClusterStruct {
 }
 
 impl NodeInfoStruct {
+    // Whether the node has room for the task and the GPU model it asks for
+    pub fn fits(&self, task: &PodSpecStruct ) -> bool {
+        let scalar_resources: bool =
+            task.cpu_milli <= self.cpu_rem &&
+            task.memory_mib <= self.mem_rem;
+
+        // Aggregates only track compute, so GPU memory requests need a look at each GPU
+        let gpu_resources: bool = if task.gpu_memory_mib > 0 && task.num_gpu > 0 {
+            self.gpu_rem.iter().filter(|gpu| gpu.borrow().fits( task )).count() >= task.num_gpu
+        } else {
+            task.gpu_milli <= self.gpu_part ||
+            task.num_gpu <= self.gpu_full
+        };
+
+        let model_match: bool =
+            task.model.is_empty() ||
+            task.model.intersects( self.spec.model.clone() );
+
+        scalar_resources && gpu_resources && model_match
+    }
+
     pub fn filter_gpus(&self, task: PodSpec ) -> impl Iterator<Item=GpuInfo>  {
 
         // Clone the GPU vector to avoid returning references tied to a temporary RefCell borrow.
         // This preserves the iterator API while sidestepping lifetime issues.
         self.gpu_rem
             .iter()
+            .filter(move |gpu| gpu.borrow().fits( &task ) )
             .cloned()
-            .filter(move |gpu| task.gpu_milli <= gpu.borrow().gpu_milli )
+    }
+
+    // GPU memory left across the node's GPUs whose memory is known
+    pub fn gpu_memory_mib(&self) -> MEM {
+        self.gpu_rem.iter().filter_map(|gpu| gpu.borrow().gpu_memory_mib ).sum()
+    }
+
+    pub fn frag_state(&self) -> FragState {
+        FragState {
+            cpu_rem: self.cpu_rem,
+            mem_rem: self.mem_rem,
+            model: self.spec.model.clone(),
+            gpu_rem: self.gpu_rem.iter().map(|gpu| gpu.borrow().gpu_milli ).collect(),
+            mig: self.gpu_rem.iter().map(|gpu| gpu.borrow().mig ).collect(),
+            gpu_memory_mib: self.gpu_rem.iter().map(|gpu| gpu.borrow().gpu_memory_mib ).collect(),
+        }
+    }
+}
+
+// Plain copy of the node resources that fragmentation depends on.
+// Lets us evaluate hypothetical placements without touching the shared GPU cells.
+#[derive(Debug, Clone)]
+#[public]
+struct FragState {
+    cpu_rem: CPU,
+    mem_rem: MEM,
+    model: MODEL,
+    gpu_rem: Vec<GPU>,
+    mig: Vec<Option<Slices>>,
+    gpu_memory_mib: Vec<Option<MEM>>,
+}
+
+impl FragState {
+
+    // Largest share a task could get on the GPU
+    pub fn usable(&self, id: NUM ) -> GPU {
+        self.mig[id].map_or(self.gpu_rem[id], |slices| slices.usable())
+    }
+
+    // Free MIG instance the task's per-GPU share and memory would get on the GPU
+    fn slot(&self, id: NUM, task: &PodSpecStruct ) -> Option<Slot> {
+        self.mig[id]?.slot( task.per_gpu_milli(), task.gpu_memory_mib, self.model.gpu_memory_mib()? )
+    }
+
+    // Whether the task could get its per-GPU share and memory on the GPU, and an instance for both on MIG GPUs
+    fn fits_gpu(&self, id: NUM, task: &PodSpecStruct ) -> bool {
+        task.per_gpu_milli() <= self.usable(id) &&
+            self.gpu_memory_mib[id].is_none_or(|gpu_memory_mib| task.gpu_memory_mib <= gpu_memory_mib ) &&
+            (self.mig[id].is_none() || self.slot( id, task ).is_some())
+    }
+
+    // Hand the task's share and memory out on the GPU, as bind_task would
+    fn take(&mut self, id: NUM, task: &PodSpecStruct ) {
+        let (gpu_milli, gpu_memory_mib) = match (self.mig[id], self.slot( id, task )) {
+            (Some(slices), Some(slot)) => {
+                self.mig[id] = Some(slices.claim( slot ));
+                (slot.gpu_milli(), slot.gpu_memory_mib( self.model.gpu_memory_mib().unwrap_or_default() ))
+            },
+            _ => (task.per_gpu_milli(), task.gpu_memory_mib),
+        };
+
+        self.gpu_rem[id] -= gpu_milli;
+        self.gpu_memory_mib[id] = self.gpu_memory_mib[id].map(|left| left - gpu_memory_mib );
+    }
+
+    fn fits(&self, task: &PodSpecStruct ) -> bool {
+        let scalar_resources: bool =
+            task.cpu_milli <= self.cpu_rem &&
+            task.memory_mib <= self.mem_rem;
+
+        let model_match: bool =
+            task.model.is_empty() ||
+            task.model.intersects( self.model.clone() );
+
+        scalar_resources && model_match
+    }
+
+    // GPUs a task could go on: the fullest GPU that fits for a share of one, the first idle ones otherwise.
+    // None if the task does not fit
+    pub fn place(&self, task: &PodSpecStruct ) -> Option<Vec<NUM>> {
+        if !self.fits( task ) { return None; }
+
+        if task.num_gpu == 0 { return Some(vec![]); }
+
+        if task.single_gpu() {
+            return self.gpu_rem.iter()
+                .enumerate()
+                .filter(|&(id, _)| self.fits_gpu( id, task ) )
+                .min_by_key(|&(_, &gpu_milli)| gpu_milli )
+                .map(|(id, _)| vec![id]);
+        }
+
+        let gpus: Vec<NUM> = (0..self.gpu_rem.len())
+            .filter(|&id| self.fits_gpu( id, task ) )
+            .take(task.num_gpu)
+            .collect();
+
+        if gpus.len() < task.num_gpu { None } else { Some(gpus) }
+    }
+
+    // As if the bound task were gone
+    pub fn release(&mut self, binding: &Binding ) {
+        self.cpu_rem += binding.task.cpu_milli;
+        self.mem_rem += binding.task.memory_mib;
+
+        binding.pick.1.iter().for_each(|gpu| {
+            let id = gpu.borrow().id;
+            self.gpu_rem[id] += binding.gpu_milli();
+            self.mig[id] = self.mig[id].map(|slices| binding.released( slices ));
+            self.gpu_memory_mib[id] = self.gpu_memory_mib[id].map(|gpu_memory_mib| gpu_memory_mib + binding.gpu_memory_mib );
+        });
+    }
+
+    // Reverse of release
+    pub fn claim(&mut self, binding: &Binding ) {
+        self.cpu_rem -= binding.task.cpu_milli;
+        self.mem_rem -= binding.task.memory_mib;
+
+        binding.pick.1.iter().for_each(|gpu| {
+            let id = gpu.borrow().id;
+            self.gpu_rem[id] -= binding.gpu_milli();
+            self.mig[id] = self.mig[id].map(|slices| binding.claimed( slices ));
+            self.gpu_memory_mib[id] = self.gpu_memory_mib[id].map(|gpu_memory_mib| gpu_memory_mib - binding.gpu_memory_mib );
+        });
+    }
+
+    // Fragmentation of this node as seen by a single task (FGD, ATC'23).
+    // Unallocated GPU milli that the task could not make use of if it arrived now:
+    // everything if the task cannot run here at all, otherwise only GPUs with too little left.
+    // Free MIG slices count when no instance large enough for the task can be laid out on them,
+    // and GPUs count when they have too little memory left for it
+    pub fn task_frag(&self, task: &PodSpecStruct ) -> GPU {
+        let gpu_unallocated: GPU = self.gpu_rem.iter().sum();
+
+        // CPU-only tasks cannot use any of the GPUs
+        if !self.fits( task ) || task.num_gpu == 0 {
+            return gpu_unallocated;
+        }
+
+        let (usable, frag) = self.gpu_rem.iter()
+            .enumerate()
+            .fold((0, 0), |(usable, frag), (id, &gpu_milli)| {
+                if self.fits_gpu( id, task ) { (usable + 1, frag) } else { (usable, frag + gpu_milli) }
+            });
+
+        if usable < task.num_gpu { gpu_unallocated } else { frag }
+    }
+
+    // Expected fragmentation of this node, weighted by the workload's task-type distribution
+    pub fn node_frag(&self, workload: &WorkloadStruct ) -> GPU {
+        let frag: GPU = workload.task_count.iter()
+            .map(|(task, &count)| count as GPU * self.task_frag(task) )
+            .sum();
+
+        frag.checked_div( workload.num_tasks as GPU ).unwrap_or(0)
+    }
+
+    // Fragmentation increase from placing the task on its best GPU(s) here, given current frag.
+    // FRAG::MAX if the task does not fit.
+    pub fn frag_delta(&self, task: &PodSpecStruct, gpu_frag: GPU, workload: &WorkloadStruct ) -> FRAG {
+        self.best_gpus( task, workload )
+            .map(|(_, frag)| frag as FRAG - gpu_frag as FRAG )
+            .unwrap_or(FRAG::MAX)
+    }
+
+    // Indices of the GPUs that minimize fragmentation after placement, and the resulting frag
+    pub fn best_gpus(&self, task: &PodSpecStruct, workload: &WorkloadStruct ) -> Option<(Vec<NUM>, GPU)> {
+        if !self.fits( task ) { return None; }
+
+        let mut after = self.clone();
+        after.cpu_rem -= task.cpu_milli;
+        after.mem_rem -= task.memory_mib;
+
+        if task.num_gpu == 0 {
+            return Some((vec![], after.node_frag( workload )));
+        }
+
+        if !task.single_gpu() {
+            // Whole GPUs are interchangeable
+            let gpus: Vec<NUM> = (0..self.gpu_rem.len())
+                .filter(|&id| self.fits_gpu( id, task ) )
+                .take(task.num_gpu)
+                .collect();
+
+            if gpus.len() < task.num_gpu { return None; }
+
+            gpus.iter().for_each(|&id| after.take( id, task ));
+            return Some((gpus, after.node_frag( workload )));
+        }
+
+        // GPUs with the same amount, layout and memory left are interchangeable, so try each once
+        let mut tried: Vec<(GPU, Option<Slices>, Option<MEM>)> = Vec::with_capacity(self.gpu_rem.len());
+
+        self.gpu_rem.iter()
+            .enumerate()
+            .filter(|&(id, _)| self.fits_gpu( id, task ) )
+            .filter(|&(id, &gpu_milli)| {
+                let left = (gpu_milli, self.mig[id], self.gpu_memory_mib[id]);
+                let new = !tried.contains(&left);
+                tried.push(left);
+                new
+            })
+            .map(|(id, _)| {
+                after.take( id, task );
+                let frag = after.node_frag( workload );
+                (after.gpu_rem[id], after.mig[id], after.gpu_memory_mib[id]) = (self.gpu_rem[id], self.mig[id], self.gpu_memory_mib[id]);
+
+                (vec![id], frag)
+            })
+            .min_by_key(|(_, frag)| *frag)
     }
 }
 
@@ -228,14 +857,18 @@ impl std::fmt::Display for NodeMetrics {
 
         writeln!(f, "Unallocated GPU resources:  {:.1} -- ({:.2}% allocation rate)",
                  self.gpu_unallocated as f64 / GPU_MILLI as f64,
-                 self.alloc_rate * 100.0 )
+                 self.alloc_rate * 100.0 )?;
+
+        writeln!(f, "Fragmented GPU resources:  {:.1} -- ({:.2}% fragmentation rate)",
+                 self.frag_total as f64 / GPU_MILLI as f64,
+                 self.frag_rate * 100.0 )
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use rand::prelude::IteratorRandom;
+    use rand::prelude::{IteratorRandom, SliceRandom};
     use rstest::{fixture, rstest};
     use std::fs::File;
 
@@ -256,18 +889,25 @@ mod tests {
         openb-pod-7432,8000,30517,1,470,,BE,Pending,12791960,12792838,
         openb-pod-0505,3152,5600,1,810,,BE,Failed,10212626,10212773,10212626";
 
-        Rc::new(WorkloadStruct::new(String::from("workload"), str.as_bytes()))
+        Rc::new(WorkloadStruct::new(String::from("workload"), WorkloadMode::Sample, 0, str.as_bytes()).unwrap())
     }
 
     #[fixture]
     fn node_csv() -> File {
-        File::open(&"clusterdata/node_data/all_nodes.csv").expect("node file not found")
+        File::open("clusterdata/node_data/all_nodes.csv").expect("node file not found")
+    }
+
+    // Workload and cluster for traces small enough to write out in the test
+    fn small_cluster( pod_csv: &str, node_csv: &str ) -> (Workload, ClusterStruct) {
+        let workload = Rc::new(WorkloadStruct::new(String::from("workload"), WorkloadMode::Sample, 0, pod_csv.as_bytes()).unwrap());
+        let cluster = ClusterStruct::new(String::from("cluster"), 0, node_csv.as_bytes(), workload.clone()).unwrap();
+        (workload, cluster)
     }
 
     #[rstest]
     fn test_create( node_csv: impl Read, workload: Workload ) {
 
-        let cluster = ClusterStruct::new(String::from("cluster"), node_csv, workload.clone());
+        let cluster = ClusterStruct::new(String::from("cluster"), 0, node_csv, workload.clone()).unwrap();
 
         println!("Cluster: {}", cluster);
 
@@ -276,7 +916,7 @@ mod tests {
     #[rstest]
     fn test_filter( node_csv: impl Read, workload: Workload ) {
 
-        let cluster = ClusterStruct::new(String::from("cluster"), node_csv, workload.clone());
+        let cluster = ClusterStruct::new(String::from("cluster"), 0, node_csv, workload.clone()).unwrap();
 
         for task in workload.tasks.iter() {
             let nodes = cluster.filter_nodes( task.clone() );
@@ -290,7 +930,7 @@ mod tests {
     #[rstest]
     fn test_bind( node_csv: impl Read, workload: Workload ) {
 
-        let cluster = ClusterStruct::new(String::from("cluster"), node_csv, workload.clone());
+        let cluster = ClusterStruct::new(String::from("cluster"), 0, node_csv, workload.clone()).unwrap();
 
         let task = workload.next_task();
         println!("Task:{}\n{}",task.id, task);
@@ -309,4 +949,336 @@ mod tests {
 
         println!("{}", cluster.metrics.borrow().alloc_rate)
     }
-}
\ No newline at end of file
+
+    #[rstest]
+    fn test_frag( node_csv: impl Read, workload: Workload ) {
+
+        let cluster = ClusterStruct::new(String::from("cluster"), 0, node_csv, workload.clone()).unwrap();
+
+        for _ in 0..100 {
+            let task = workload.next_task();
+
+            let nodes = cluster.filter_nodes( task.clone() );
+            let Some(node) = nodes.choose( &mut cluster.rng.borrow_mut() ) else { continue };
+
+            let gpus = node.borrow()
+                .filter_gpus( task.clone() )
+                .take(task.num_gpu)
+                .collect();
+
+            cluster.bind_task( task.clone(), (node.clone(), gpus) );
+        }
+
+        // Incremental bookkeeping agrees with a full recomputation
+        let frag_total: GPU = cluster.nodes.iter().map(|node| {
+            let node = node.borrow();
+            assert_eq!(node.gpu_frag, node.frag_state().node_frag( &workload ));
+            node.gpu_frag
+        }).sum();
+
+        // Cached deltas agree with recomputing them from scratch
+        for task in workload.tasks.iter() {
+            for node in cluster.nodes.iter() {
+                let node = node.borrow();
+                let state = node.frag_state();
+
+                assert_eq!(cluster.frag_delta( task, &node ),
+                           state.frag_delta( task, node.gpu_frag, &workload ));
+            }
+        }
+
+        let metrics = cluster.metrics.borrow();
+        assert_eq!(metrics.frag_total, frag_total);
+        assert!(metrics.frag_total <= metrics.gpu_unallocated);
+
+        println!("{}", metrics);
+    }
+
+    #[rstest]
+    fn test_unbind( node_csv: impl Read, workload: Workload ) {
+
+        let cluster = ClusterStruct::new(String::from("cluster"), 0, node_csv, workload.clone()).unwrap();
+        let before = cluster.metrics.borrow().clone();
+
+        let mut bound = Vec::new();
+        for _ in 0..100 {
+            let task = workload.next_task();
+
+            let nodes = cluster.filter_nodes( task.clone() );
+            let Some(node) = nodes.choose( &mut cluster.rng.borrow_mut() ) else { continue };
+
+            let gpus = node.borrow()
+                .filter_gpus( task.clone() )
+                .take(task.num_gpu)
+                .collect();
+
+            let bind = cluster.bind_task( task.clone(), (node.clone(), gpus) );
+            bound.push((task, bind));
+        }
+
+        // Release in a different order than bound
+        bound.shuffle( &mut cluster.rng.borrow_mut() );
+        for (task, bind) in bound.iter() {
+            assert!(cluster.is_bound( task ));
+            assert!(cluster.unbind( task.id, *bind ).is_some());
+        }
+
+        // Cluster is back to empty
+        for node in cluster.nodes.iter() {
+            let node = node.borrow();
+
+            assert_eq!(node.cpu_rem, node.spec.cpu_milli);
+            assert_eq!(node.mem_rem, node.spec.memory_mib);
+            assert_eq!(node.gpu_full, node.spec.num_gpu);
+            assert_eq!(node.gpu_part, 0);
+            assert_eq!(node.gpu_unallocated, node.spec.gpu_milli);
+            assert!(node.gpu_rem.iter().all(|gpu| gpu.borrow().gpu_milli == GPU_MILLI ));
+
+            for task in workload.tasks.iter() {
+                assert_eq!(cluster.frag_delta( task, &node ),
+                           node.frag_state().frag_delta( task, node.gpu_frag, &workload ));
+            }
+        }
+
+        let after = cluster.metrics.borrow();
+        assert_eq!(after.gpu_unallocated, before.gpu_unallocated);
+        assert_eq!(after.frag_total, before.frag_total);
+
+        // Nothing left to release
+        let (task, bind) = &bound[0];
+        assert!(cluster.unbind( task.id, *bind ).is_none());
+    }
+
+    #[rstest]
+    fn test_placements( node_csv: impl Read, workload: Workload ) {
+
+        let cluster = ClusterStruct::new(String::from("cluster"), 0, node_csv, workload.clone()).unwrap();
+
+        let mut bound = Vec::new();
+        for _ in 0..100 {
+            let task = workload.next_task();
+
+            let nodes = cluster.filter_nodes( task.clone() );
+            let Some(node) = nodes.choose( &mut cluster.rng.borrow_mut() ) else { continue };
+
+            let gpus: Vec<GpuInfo> = node.borrow()
+                .filter_gpus( task.clone() )
+                .take(task.num_gpu)
+                .collect();
+
+            let bind = cluster.bind_task( task.clone(), (node.clone(), gpus.clone()) );
+
+            // The registry reports the pick it was given
+            let placement = cluster.placement( task.id ).unwrap();
+            assert_eq!(placement.bind, bind);
+            assert_eq!(placement.node, node.borrow().spec.id);
+            assert_eq!((&placement.pod_name, &placement.node_name), (&task.name, &node.borrow().spec.name));
+            assert_eq!(placement.gpus, gpus.iter().map(|gpu| gpu.borrow().id ).collect::<Vec<_>>());
+            assert_eq!(placement.gpu_milli, task.per_gpu_milli());
+
+            bound.push((task, bind));
+        }
+
+        assert_eq!(cluster.placements().len(), bound.len());
+
+        // Placements account for everything the nodes have given out
+        for node in cluster.nodes.iter() {
+            let node = node.borrow();
+
+            let cpu: CPU = node.pods.iter().map(|p| workload.tasks[p.pod].cpu_milli ).sum();
+            let gpu: GPU = node.pods.iter().map(|p| p.gpu_milli * p.gpus.len() as GPU ).sum();
+            assert_eq!(node.cpu_rem + cpu, node.spec.cpu_milli);
+            assert_eq!(node.gpu_unallocated + gpu, node.spec.gpu_milli);
+
+            assert_eq!(node.to_string().matches("POD:").count(), node.pods.len());
+        }
+
+        for (task, bind) in bound.iter() {
+            cluster.unbind( task.id, *bind );
+        }
+        assert!(cluster.placements().is_empty());
+        assert!(cluster.placement( bound[0].0.id ).is_none());
+    }
+
+    #[rstest]
+    fn test_preemption() {
+        let pod_csv =
+        "name,cpu_milli,memory_mib,num_gpu,gpu_milli,gpu_spec,qos
+        be-full,4000,8192,1,1000,,BE
+        be-half,4000,8192,1,500,,BE
+        be-half-2,4000,8192,1,500,,BE
+        burstable-full,4000,8192,1,1000,,Burstable
+        ls-half,4000,8192,1,500,,LS
+        ls-two,8000,16384,2,1000,,LS";
+        let node_csv =
+        "sn,cpu_milli,memory_mib,gpu,model
+        node-0,96000,262144,2,";
+
+        let (workload, cluster) = small_cluster( pod_csv, node_csv );
+        let [be_full, be_half, be_half_2, burstable, ls_half, ls_two] = workload.tasks.clone().try_into().unwrap();
+
+        let node = cluster.nodes[0].clone();
+        let gpu = |id: usize| node.borrow().gpu_rem[id].clone();
+        cluster.bind_task( be_full, (node.clone(), vec![gpu(0)]) );
+        cluster.bind_task( be_half, (node.clone(), vec![gpu(1)]) );
+        cluster.bind_task( be_half_2, (node.clone(), vec![gpu(1)]) );
+        assert!(cluster.filter_nodes( ls_half.clone() ).next().is_none());
+
+        // Nothing to evict from on nodes the scheduler would not use
+        assert!(cluster.preemption_victims( &ls_half, |node| node.spec.id != 0 ).is_none());
+
+        // Evicting the newest half GPU task is enough
+        let (_, victims, gpus) = cluster.preemption_victims( &ls_half, |_| true ).unwrap();
+        assert_eq!(victims.iter().map(|b| b.task.id ).collect::<Vec<_>>(), vec![2]);
+        assert_eq!(gpus, vec![1]);
+
+        // Two whole GPUs need everything gone
+        let (_, victims, gpus) = cluster.preemption_victims( &ls_two, |_| true ).unwrap();
+        assert_eq!(victims.len(), 3);
+        assert_eq!(gpus, vec![0, 1]);
+
+        for victim in victims {
+            assert!(cluster.unbind( victim.task.id, victim.bind ).is_some());
+        }
+        assert!(cluster.residents( &node.borrow() ).is_empty());
+        assert_eq!(node.borrow().gpu_unallocated, node.borrow().spec.gpu_milli);
+
+        // Only best-effort tasks can be evicted
+        cluster.bind_task( burstable, (node.clone(), vec![gpu(0)]) );
+        assert!(cluster.preemption_victims( &ls_two, |_| true ).is_none());
+    }
+
+    #[rstest]
+    fn test_mig() {
+        let pod_csv =
+        "name,cpu_milli,memory_mib,num_gpu,gpu_milli,gpu_spec
+        small,4000,8192,1,100,A100
+        half,4000,8192,1,500,A100
+        third,4000,8192,1,400,A100";
+        let node_csv =
+        "sn,cpu_milli,memory_mib,gpu,model
+        node-0,96000,262144,1,A100";
+
+        let (workload, cluster) = small_cluster( pod_csv, node_csv );
+        let [small, half, third] = workload.tasks.clone().try_into().unwrap();
+
+        let node = cluster.nodes[0].clone();
+        let gpu = node.borrow().gpu_rem[0].clone();
+
+        // Tasks hold their whole instance: 1g at the top, then 4g at offset 0
+        cluster.bind_task( small.clone(), (node.clone(), vec![gpu.clone()]) );
+        let half_bind = cluster.bind_task( half.clone(), (node.clone(), vec![gpu.clone()]) );
+
+        let slots: Vec<String> = node.borrow().pods.iter().map(|p| p.slot.unwrap().to_string() ).collect();
+        assert_eq!(slots, ["1g@6", "4g@0"]);
+        assert_eq!(gpu.borrow().gpu_milli, GPU_MILLI - 143 - 571);
+        assert_eq!(node.borrow().gpu_unallocated, gpu.borrow().gpu_milli);
+        assert!(cluster.check_aggregates( &node ).is_ok());
+
+        // Two slices are free, so a third of a GPU does not fit, and all of them are fragmented for it
+        assert_eq!(gpu.borrow().usable(), 285);
+        assert!(cluster.filter_nodes( third.clone() ).next().is_none());
+        assert_eq!(node.borrow().frag_state().task_frag( &third ), gpu.borrow().gpu_milli);
+        assert_eq!(node.borrow().frag_state().task_frag( &small ), 0);
+
+        // Released instances free their slices again
+        cluster.unbind( half.id, half_bind );
+        assert_eq!(gpu.borrow().mig.unwrap().to_string(), "......#");
+        assert!(cluster.filter_nodes( third ).next().is_some());
+        assert!(cluster.check_aggregates( &node ).is_ok());
+    }
+
+    #[rstest]
+    #[should_panic(expected = "without a free instance")]
+    fn test_mig_no_instance() {
+        let pod_csv =
+        "name,cpu_milli,memory_mib,num_gpu,gpu_milli,gpu_spec
+        half,4000,8192,1,500,A100";
+        let node_csv =
+        "sn,cpu_milli,memory_mib,gpu,model
+        node-0,96000,262144,1,A100";
+
+        let (workload, cluster) = small_cluster( pod_csv, node_csv );
+
+        // The 4g instance at offset 0 is the only one for half a GPU, so a second half has nowhere to go
+        let node = cluster.nodes[0].clone();
+        let gpu = node.borrow().gpu_rem[0].clone();
+        let half = workload.tasks[0].clone();
+
+        cluster.bind_task( half.clone(), (node.clone(), vec![gpu.clone()]) );
+        cluster.bind_task( half, (node, vec![gpu]) );
+    }
+
+    #[rstest]
+    fn test_gpu_memory() {
+        let pod_csv =
+        "name,cpu_milli,memory_mib,num_gpu,gpu_milli,gpu_memory_mib,gpu_spec
+        large,4000,8192,1,250,12288,V100M16
+        small,4000,8192,1,250,2048,V100M16
+        wide,4000,8192,1,250,8192,V100M16";
+        let node_csv =
+        "sn,cpu_milli,memory_mib,gpu,model
+        node-0,96000,262144,1,V100M16";
+
+        let (workload, cluster) = small_cluster( pod_csv, node_csv );
+        let [large, small, wide] = workload.tasks.clone().try_into().unwrap();
+
+        let node = cluster.nodes[0].clone();
+        let gpu = node.borrow().gpu_rem[0].clone();
+        assert_eq!(gpu.borrow().gpu_memory_mib, Some(16384));
+
+        let bind = cluster.bind_task( large.clone(), (node.clone(), vec![gpu.clone()]) );
+        assert_eq!(gpu.borrow().gpu_memory_mib, Some(4096));
+        assert!(cluster.check_aggregates( &node ).is_ok());
+
+        // Three quarters of the GPU are left, but not the memory for a second large task
+        assert!(node.borrow().filter_gpus( wide.clone() ).next().is_none());
+        assert!(cluster.filter_nodes( wide.clone() ).next().is_none());
+        assert!(cluster.filter_nodes( small.clone() ).next().is_some());
+        assert_eq!(node.borrow().frag_state().task_frag( &wide ), gpu.borrow().gpu_milli);
+        assert_eq!(node.borrow().frag_state().task_frag( &small ), 0);
+
+        cluster.unbind( large.id, bind );
+        assert_eq!(gpu.borrow().gpu_memory_mib, Some(16384));
+        assert!(cluster.filter_nodes( wide ).next().is_some());
+    }
+
+    #[rstest]
+    fn test_mig_memory() {
+        let pod_csv =
+        "name,cpu_milli,memory_mib,num_gpu,gpu_milli,gpu_memory_mib,gpu_spec
+        small,4000,8192,1,100,6144,A100
+        large,4000,8192,1,100,24576,A100
+        huge,4000,8192,1,100,49152,A100";
+        let node_csv =
+        "sn,cpu_milli,memory_mib,gpu,model
+        node-0,96000,262144,1,A100";
+
+        let (workload, cluster) = small_cluster( pod_csv, node_csv );
+        let [small, large, huge] = workload.tasks.clone().try_into().unwrap();
+
+        let node = cluster.nodes[0].clone();
+        let gpu = node.borrow().gpu_rem[0].clone();
+
+        // No instance has more memory than the GPU
+        assert!(cluster.filter_nodes( huge.clone() ).next().is_none());
+        assert!(cluster.validate_pick( &huge, &(node.clone(), vec![gpu.clone()]) ).is_err());
+
+        // A tenth of a GPU with 6GiB needs a 2g.10gb instance, and holds all of its memory
+        let bind = cluster.bind_task( small.clone(), (node.clone(), vec![gpu.clone()]) );
+        assert_eq!(node.borrow().pods[0].slot.unwrap().to_string(), "2g@4");
+        assert_eq!(node.borrow().pods[0].gpu_memory_mib, 10 * MEM_MIB);
+        assert_eq!(gpu.borrow().gpu_memory_mib, Some(30 * MEM_MIB));
+        assert!(cluster.check_aggregates( &node ).is_ok());
+
+        // 24GiB takes the whole GPU, so there is room for its share and memory but no instance
+        assert!(gpu.borrow().gpu_memory_mib.unwrap() >= large.gpu_memory_mib);
+        assert!(cluster.filter_nodes( large.clone() ).next().is_none());
+        assert!(cluster.validate_pick( &large, &(node.clone(), vec![gpu.clone()]) ).is_err());
+        assert_eq!(node.borrow().frag_state().task_frag( &large ), gpu.borrow().gpu_milli);
+
+        cluster.unbind( small.id, bind );
+        assert_eq!(gpu.borrow().gpu_memory_mib, Some(40 * MEM_MIB));
+        assert!(cluster.filter_nodes( large ).next().is_some());
+    }
+}
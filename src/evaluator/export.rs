@@ -0,0 +1,102 @@
+use crate::evaluator::*;
+use crate::csv_reader::manifest::{write_yaml, Object};
+use std::io::Write;
+use std::path::Path;
+
+// Export: the cluster as scheduled, before the batch is deployed, as Kubernetes manifests.
+// Nodes with their inventory, then every bound pod with `nodeName` and the GPU index annotation set
+
+impl ClusterStruct {
+
+    // Node and pod manifests, in node order, under their trace names. Sampling may bind
+    // the same pod more than once, so each of those copies gets its bind number appended
+    pub fn manifests(&self) -> Vec<Object> {
+        let bindings = self.bindings.borrow();
+
+        let nodes = self.nodes.iter()
+            .map(|node| node.borrow().spec.to_manifest());
+
+        let pods = self.placements().into_iter()
+            .filter_map(|placement| {
+                let binds = bindings.get(&placement.pod)?;
+                let binding = binds.iter().find(|b| b.bind == placement.bind )?;
+
+                let name = match binds.len() {
+                    1 => placement.pod_name.to_string(),
+                    _ => format!("{}-{}", placement.pod_name, placement.bind ),
+                };
+                Some(binding.task.to_manifest( &name, placement.node_name.as_str(), &placement.gpus ))
+            });
+
+        nodes.chain(pods).collect::<Vec<_>>()
+    }
+
+    pub fn write_manifests(&self, writer: impl Write ) -> Result<()> {
+        write_yaml( writer, &self.manifests() )
+    }
+
+    // Write to `batch_{n}.yaml` in the directory, counting batches from 1
+    pub fn export(&self, dir: &Path, batch_num: usize ) -> Result<()> {
+        let file = crate::error::create( dir.join(format!("batch_{}.yaml", batch_num + 1)) )?;
+
+        let mut writer = std::io::BufWriter::new(file);
+        self.write_manifests( &mut writer )?;
+        writer.flush().map_err(Error::Write)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::csv_reader::process_yaml;
+    use crate::heuristics::parse_decider;
+    use crate::heuristics::simple_schedulers::gpu_packing_scheduler;
+    use rstest::rstest;
+    use serde::Deserialize;
+    use std::fs::File;
+
+    #[rstest]
+    fn test_export() {
+        let pod_csv = File::open("clusterdata/pod_data/default.csv").expect("pod file not found");
+        let node_csv = File::open("clusterdata/node_data/gpu_nodes.csv").expect("node file not found");
+
+        let mut eval = Evaluator::new( Box::new( FnScheduler::new( "gpu_packing", gpu_packing_scheduler )),
+                                       parse_decider("max_tasks_arrived=200").unwrap(), WorkloadMode::Sample, 0, pod_csv, node_csv ).unwrap();
+        eval.schedule().unwrap();
+
+        let cluster = &eval.cluster;
+        let mut yaml = Vec::new();
+        cluster.write_manifests( &mut yaml ).unwrap();
+
+        // Node and pod specs read back as they were, pods in placement order
+        let specs = |cluster: &ClusterStruct| cluster.nodes.iter().map(|node| node.borrow().spec.clone() ).collect::<Vec<_>>();
+        let exported = ClusterStruct::new( String::from("exported"), 0, yaml.as_slice(), eval.workload.clone() ).unwrap();
+        assert_eq!(specs( &exported ), specs( cluster ));
+
+        let placements = cluster.placements();
+        let mut pods = Vec::new();
+        process_yaml( yaml.as_slice(), |_, pod: PodSpecStruct| { pods.push( pod ); Ok(()) }).unwrap();
+        assert_eq!(pods.len(), placements.len());
+
+        let bindings = cluster.bindings.borrow();
+        for (pod, placement) in pods.iter().zip( &placements ) {
+            let task = &bindings[&placement.pod][0].task;
+            assert_eq!((pod.key(), pod.qos), (task.key(), task.qos));
+        }
+
+        // Bound where they were placed
+        let documents: Vec<serde_norway::Value> = serde_norway::Deserializer::from_slice( &yaml )
+            .map(|document| serde_norway::Value::deserialize( document ).unwrap())
+            .filter(|object| object["kind"] == "Pod" )
+            .collect();
+
+        for (object, placement) in documents.iter().zip( &placements ) {
+            assert_eq!(object["spec"]["nodeName"], placement.node_name.as_str());
+            assert!(object["metadata"]["name"].as_str().unwrap().starts_with( placement.pod_name.as_str() ));
+
+            let index = object["metadata"]["annotations"].get( crate::csv_reader::manifest::GPU_INDEX ).and_then(|v| v.as_str());
+            let gpus: Vec<String> = placement.gpus.iter().map(NUM::to_string).collect();
+            assert_eq!(index.unwrap_or_default(), gpus.join("-"));
+        }
+    }
+}
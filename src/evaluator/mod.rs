@@ -1,13 +1,23 @@
+use std::cell::RefCell;
 use std::io::Read;
+use std::path::PathBuf;
 use std::rc::Rc;
-use crate::csv_reader::process_csv;
+use rand::rngs::StdRng;
+use rand::{RngCore, SeedableRng};
+use crate::csv_reader::process_input;
+use crate::error::{Error, Result};
 use crate::types::*;
 
 pub mod workload;
 pub mod cluster;
+pub mod report;
+pub mod validate;
+pub mod export;
 
 use workload::*;
 use cluster::*;
+use report::*;
+use validate::*;
 
 
 
@@ -17,44 +27,119 @@ struct Evaluator
     // Dependency injection functions that alter Evaluator behavior
     // Can make non-mutable queries on Cluster and Workload objects
     // Evaluator applies decision and evaluates performance metrics
-    scheduler: ScheduleFunc,
-    decider: DeployFunc,
+    // Scheduler is mutably borrowed while it decides, so it must not reach back into itself
+    scheduler: RefCell<BoxedScheduler>,
+    decider: RefCell<BoxedDecider>,
 
     // Tasks to be scheduled
     workload: Workload,
 
     // Compute Nodes
     cluster: Cluster,
+
+    // Workload and Cluster random streams are derived from it, so runs can be reproduced
+    seed: SEED,
+
+    // Let latency-sensitive tasks that did not fit evict best-effort ones
+    preemption: bool,
+
+    // Check every pick and the cluster bookkeeping after every bind. Stops at the first violation
+    strict: bool,
+
+    // Directory to write each batch's placements to, as Kubernetes manifests
+    export: Option<PathBuf>,
 }
 
-const NUM_LOOPS: usize = 100;
+// How evaluate reports results
+#[derive(Debug, Clone, Copy)]
+#[derive(PartialEq, Eq)]
+#[derive(clap::ValueEnum)]
+pub enum OutputFormat {
+    // Human-readable, one line per batch plus averages
+    Text,
+    // One CSV row per batch
+    Csv,
+    // Full report once all batches are done
+    Json,
+}
 
 impl Evaluator {
 
     pub fn new(
-        scheduler: ScheduleFunc,
-        decider: DeployFunc,
+        scheduler: BoxedScheduler,
+        decider: BoxedDecider,
+        mode: WorkloadMode,
+        seed: SEED,
         workload_reader: impl Read,
         cluster_reader: impl Read,
-    ) -> Self {
-        let workload = WorkloadStruct::new(String::from("workload"), workload_reader);
+    ) -> Result<Self> {
+        // Independent streams, so a change in one does not shift the other
+        let mut seeder = StdRng::seed_from_u64(seed);
+
+        let workload = WorkloadStruct::new(String::from("workload"), mode, seeder.next_u64(), workload_reader)?;
         let workload = Rc::new(workload);
 
-        let cluster= ClusterStruct::new(String::from("cluster"), cluster_reader, workload.clone());
+        let cluster= ClusterStruct::new(String::from("cluster"), seeder.next_u64(), cluster_reader, workload.clone())?;
 
-        Self { scheduler, decider, workload, cluster }
+        let scheduler = RefCell::new(scheduler);
+        let decider = RefCell::new(decider);
+
+        Ok(Self { scheduler, decider, workload, cluster, seed, preemption: false, strict: false, export: None })
+    }
+
+    pub fn with_preemption(mut self, preemption: bool ) -> Self {
+        self.preemption = preemption;
+        self
     }
 
-    pub fn schedule_and_deploy(&mut self) -> ( TaskMetrics, NodeMetrics ){
+    pub fn with_strict(mut self, strict: bool ) -> Self {
+        self.strict = strict;
+        self
+    }
 
-        let decider_func = self.decider;
-        let scheduler_func = self.scheduler;
+    pub fn with_export(mut self, dir: Option<PathBuf> ) -> Self {
+        self.export = dir;
+        self
+    }
+
+    pub fn scheduler_name(&self) -> String {
+        self.scheduler.borrow().name().to_string()
+    }
+
+    pub fn decider_name(&self) -> String {
+        self.decider.borrow().name()
+    }
+
+    // Schedule tasks until the decider deploys the batch
+    fn schedule(&mut self) -> Result<()> {
 
         loop {
+            // Trace replay: once every task has arrived, wait for departures to make room for delayed tasks.
+            // The batch ends when nothing is delayed, or nothing is left to depart
+            if self.workload.exhausted() {
+                match self.cluster.next_departure() {
+                    Some(time) if self.workload.backlog_size() > 0 => {
+                        self.workload.advance_to(time);
+                        self.cluster.release_until(time);
+                        self.workload.retry_backlog();
+                        continue;
+                    },
+                    _ => break,
+                }
+            }
+
             // Sample task
             let task: PodSpec = self.workload.next_task();
 
-            match scheduler_func(self, task.to_owned()) {
+            // Trace replay: free up tasks that finished before this one arrived
+            if self.cluster.release_until(self.workload.now()) > 0 {
+                self.workload.retry_backlog();
+            }
+
+            let pick = self.scheduler.borrow_mut().schedule(self, task.to_owned());
+            let pick = pick.or_else(|| self.preempt(&task));
+
+            match pick {
                 None => {
                     // Scheduling failed. Add to backload for next deployment
                     self.workload.push_backlog(task.clone());
@@ -62,8 +147,24 @@ impl Evaluator {
                     self.workload.update_metrics(task, false);
                 },
                 Some(choice) => {
+                    if self.strict {
+                        self.cluster.validate_pick(&task, &choice)
+                            .map_err(|reason| Violation { task: task.clone(), reason })?;
+                    }
+
                     // Scheduling succeeded. Apply to cluster
-                    self.cluster.bind_task(task.clone(), choice);
+                    let bind = self.cluster.bind_task(task.clone(), choice.clone());
+
+                    if self.strict {
+                        self.cluster.check_placement(&task, bind, &choice)
+                            .and_then(|_| self.cluster.check_aggregates(&choice.0))
+                            .map_err(|reason| Violation { task: task.clone(), reason })?;
+                    }
+
+                    if let Some(time) = self.workload.departure_time(&task) {
+                        self.cluster.depart_at(time, task.clone(), bind);
+                    }
+                    self.scheduler.borrow_mut().on_bind(self, &task, &choice);
 
                     self.workload.update_metrics(task, true);
                 },
@@ -71,43 +172,418 @@ impl Evaluator {
             }
             // Interrogate cluster and update performance metrics
 
-            if decider_func(self) { break; }
+            if self.decider.borrow_mut().deploy(self) { break; }
         }
 
+        Ok(())
+    }
+
+    fn deploy(&mut self) -> ( TaskMetrics, QosBreakdown, NodeMetrics ) {
         // Tasks "deployed". Return metrics
-        ( self.workload.deploy(), self.cluster.deploy() )
+        let (task_m, qos_m) = self.workload.deploy();
+        let metrics = ( task_m, qos_m, self.cluster.deploy() );
+        self.scheduler.borrow_mut().on_deploy(self);
+        self.decider.borrow_mut().on_deploy(self);
+
+        metrics
     }
 
-    pub fn evaluate(&mut self) {
+    // Preemption stage, for tasks the scheduler could not place.
+    // Evicts the fewest best-effort tasks that make room on one node the scheduler admits, back into the backlog
+    fn preempt(&self, task: &PodSpec ) -> Option<SchedulingPick> {
+        if !self.preemption || !task.qos.preempts() { return None; }
+
+        let scheduler = self.scheduler.borrow();
+        let (node_ref, victims, gpu_ids) = self.cluster.preemption_victims( task, |node| scheduler.admits( self, task, node ))?;
+
+        for victim in victims {
+            self.cluster.unbind( victim.task.id, victim.bind );
+            self.workload.evict( victim.task, victim.time );
+        }
+        self.workload.record_preemption( task );
 
-        let mut tasks_scheduled = Default::default();
-        let mut gpu_unallocated = Default::default();
+        let gpus = gpu_ids.into_iter()
+            .map(|id| node_ref.borrow().gpu_rem[id].clone())
+            .collect();
 
-        // Average metrics over a number of loops, to reduce statistical error
-        for batch_num in 0..NUM_LOOPS {
-            let (task_m, node_m) = self.schedule_and_deploy();
+        Some((node_ref, gpus))
+    }
 
-            tasks_scheduled = update_average(tasks_scheduled, task_m.tasks_scheduled as f64, batch_num ) ;
-            gpu_unallocated = update_average(gpu_unallocated, node_m.gpu_unallocated as f64, batch_num);
+    // Run a number of batches, to reduce statistical error. Prints progress in the given format
+    pub fn evaluate(&mut self, num_batches: usize, format: OutputFormat ) -> Result<Vec<BatchRecord>> {
 
-            println!("Batch {}: tasks scheduled: {}, allocation rate: {:.2}", batch_num + 1, task_m.tasks_scheduled, node_m.alloc_rate * 100.0);
+        match format {
+            OutputFormat::Text => println!("Seed: {}", self.seed),
+            OutputFormat::Csv => println!("seed,batch,tasks_arrived,tasks_scheduled,tasks_delayed,alloc_rate,frag_rate"),
+            OutputFormat::Json => {},
         }
 
-        let gpu_total = self.cluster.metrics.borrow().gpu_total;
-        let alloc_ratio = 1.0 - gpu_unallocated / gpu_total as f64;
+        (0..num_batches).map(|batch_num| {
+            let record = self.run_batch(batch_num)?;
+            let (task_m, node_m) = (&record.tasks, &record.nodes);
+
+            match format {
+                OutputFormat::Text => println!("Batch {}: tasks scheduled: {}, allocation rate: {:.2}, fragmentation rate: {:.2}",
+                         batch_num + 1, task_m.tasks_scheduled, node_m.alloc_rate * 100.0, node_m.frag_rate * 100.0),
+                OutputFormat::Csv => println!("{},{},{},{},{},{},{}",
+                         self.seed, batch_num + 1, task_m.tasks_arrived, task_m.tasks_scheduled, task_m.tasks_delayed,
+                         node_m.alloc_rate, node_m.frag_rate),
+                OutputFormat::Json => {},
+            }
+
+            Ok(record)
+        }).collect()
+    }
 
+    // Run a single batch silently
+    pub fn run_batch(&mut self, batch_num: usize ) -> Result<BatchRecord> {
+        self.schedule()?;
 
-        println!("Average tasks scheduled: {:.0}", tasks_scheduled );
-        println!("Average allocation rate : {:.2}", alloc_ratio * 100.0)
+        // Deploying resets the cluster, so placements are exported first
+        if let Some(dir) = &self.export {
+            self.cluster.export( dir, batch_num )?;
+        }
+        let (tasks, qos, nodes) = self.deploy();
+
+        Ok(BatchRecord { batch: batch_num + 1, tasks, qos, nodes })
     }
 
 }
 
-fn update_average(prev_avg: f64, x: f64, n: usize ) -> f64 {
-    if n == 1  { return x; }
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::heuristics::{parse_decider, parse_pipeline};
+    use crate::heuristics::framework::{Combine, Pipeline, ScorePlugin, Ties};
+    use crate::heuristics::plugins::{ExclusiveModel, FgdGpu, GpuPacking};
+    use crate::heuristics::simple_schedulers::gpu_packing_scheduler;
+    use rstest::{fixture, rstest};
+    use std::fs::File;
+
+    #[fixture]
+    fn pod_csv() -> File {
+        File::open("clusterdata/pod_data/default.csv").expect("pod file not found")
+    }
+
+    #[fixture]
+    fn node_csv() -> File {
+        File::open("clusterdata/node_data/gpu_nodes.csv").expect("node file not found")
+    }
 
-    let frac: f64 = n as f64 / (n + 1) as f64;
-    let contrib = x / (n + 1) as f64;
+    fn stop_after_100() -> BoxedDecider {
+        parse_decider("max_tasks_arrived=100").unwrap()
+    }
 
-    frac * prev_avg + contrib
-}
\ No newline at end of file
+    #[derive(Debug, Default)]
+    struct Counts {
+        scheduled: usize,
+        bound: usize,
+        deployed: usize,
+    }
+
+    // Delegates to GPU packing and counts hook calls
+    struct CountingScheduler {
+        counts: Rc<RefCell<Counts>>,
+    }
+
+    impl Scheduler for CountingScheduler {
+        fn name(&self) -> &str { "counting" }
+
+        fn schedule(&mut self, evaluator: &Evaluator, task: PodSpec ) -> Option<SchedulingPick> {
+            self.counts.borrow_mut().scheduled += 1;
+            gpu_packing_scheduler( evaluator, task )
+        }
+
+        fn on_bind(&mut self, _evaluator: &Evaluator, _task: &PodSpec, _pick: &SchedulingPick ) {
+            self.counts.borrow_mut().bound += 1;
+        }
+
+        fn on_deploy(&mut self, _evaluator: &Evaluator ) {
+            self.counts.borrow_mut().deployed += 1;
+        }
+    }
+
+    #[rstest]
+    fn test_scheduler_hooks( pod_csv: File, node_csv: File ) {
+        let counts = Rc::new(RefCell::new(Counts::default()));
+        let scheduler = Box::new( CountingScheduler { counts: counts.clone() } );
+
+        let mut eval = Evaluator::new( scheduler, stop_after_100(), WorkloadMode::Sample, 0, pod_csv, node_csv ).unwrap();
+        let batches = eval.evaluate( 2, OutputFormat::Json ).unwrap();
+
+        let tasks_scheduled: usize = batches.iter().map(|b| b.tasks.tasks_scheduled ).sum();
+        let tasks_seen: usize = batches.iter().map(|b| b.tasks.tasks_scheduled + b.tasks.tasks_delayed ).sum();
+
+        let counts = counts.borrow();
+        assert_eq!(eval.scheduler_name(), "counting");
+        assert_eq!(counts.scheduled, tasks_seen);
+        assert_eq!(counts.bound, tasks_scheduled);
+        assert_eq!(counts.deployed, 2);
+    }
+
+    #[rstest]
+    fn test_closure_scheduler( pod_csv: File, node_csv: File ) {
+        let mut calls = 0;
+        let scheduler = Box::new( FnScheduler::new( "closure", move |evaluator: &Evaluator, task: PodSpec| {
+            // Refuse every other task
+            calls += 1;
+            if calls % 2 == 0 { None } else { gpu_packing_scheduler( evaluator, task ) }
+        }));
+
+        let mut eval = Evaluator::new( scheduler, stop_after_100(), WorkloadMode::Sample, 0, pod_csv, node_csv ).unwrap();
+        let batch = eval.run_batch( 0 ).unwrap();
+
+        assert_eq!(eval.scheduler_name(), "closure");
+        assert!(batch.tasks.tasks_delayed >= 50);
+    }
+
+    #[rstest]
+    fn test_decider_spec( pod_csv: File, node_csv: File ) {
+        assert!(parse_decider("max_delayed=lots").is_err());
+        assert!(parse_decider("never").is_err());
+
+        // max_tasks_arrived keeps its max_delayed safety stop, unless the spec sets its own
+        assert_eq!(parse_decider("max_tasks_arrived=100").unwrap().name(), "max_tasks_arrived=100,max_delayed=500");
+        assert_eq!(parse_decider("max_delayed=50,max_tasks_arrived=100").unwrap().name(), "max_delayed=50,max_tasks_arrived=100");
+
+        // Refuses everything, so only the failure streak can end the batch
+        let scheduler = Box::new( FnScheduler::new( "refuse", |_: &Evaluator, _: PodSpec| None ));
+        let decider = parse_decider("consecutive_failures=20,target_alloc=0.99").unwrap();
+
+        let mut eval = Evaluator::new( scheduler, decider, WorkloadMode::Sample, 0, pod_csv, node_csv ).unwrap();
+        assert_eq!(eval.decider_name(), "consecutive_failures=20,target_alloc=0.99");
+
+        // The streak starts over in every batch, whether tasks are new or retried from the backlog
+        for batch_num in 0..2 {
+            let batch = eval.run_batch( batch_num ).unwrap();
+            assert_eq!(batch.tasks.tasks_delayed, 20);
+        }
+    }
+
+    #[rstest]
+    fn test_preemption( pod_csv: File ) {
+        // A slice of the cluster, so it fills up quickly
+        let nodes: String = std::io::read_to_string( node_csv() ).unwrap()
+            .lines().take(200).map(|line| format!("{}\n", line) ).collect();
+
+        let pods = std::io::read_to_string( pod_csv ).unwrap();
+        let run = |pods: &str, preemption: bool| {
+            let scheduler = Box::new( FnScheduler::new( "gpu_packing", gpu_packing_scheduler ));
+            let decider = parse_decider("max_tasks_arrived=2000").unwrap();
+            let mut eval = Evaluator::new( scheduler, decider, WorkloadMode::Sample, 0, pods.as_bytes(), nodes.as_bytes() ).unwrap()
+                .with_preemption( preemption );
+            eval.run_batch( 0 ).unwrap()
+        };
+
+        let without = run( &pods, false );
+        let with = run( &pods, true );
+
+        assert_eq!(without.tasks.preemptions, 0);
+        assert!(with.tasks.preemptions > 0);
+        assert!(with.tasks.tasks_preempted >= with.tasks.preemptions);
+
+        // Latency-sensitive tasks evict, best-effort tasks are evicted
+        let ls = &with.qos[&Qos::LS];
+        let be = &with.qos[&Qos::BE];
+        assert_eq!(ls.preemptions, with.tasks.preemptions);
+        assert_eq!(be.tasks_preempted, with.tasks.tasks_preempted);
+        assert!(ls.tasks_scheduled > without.qos[&Qos::LS].tasks_scheduled);
+
+        // Evicted tasks count as scheduled again only once they are bound again, so totals match the cluster
+        for run in [&without, &with] {
+            assert_eq!(run.tasks.total_gpu, run.nodes.gpu_total - run.nodes.gpu_unallocated);
+        }
+        assert!(with.tasks.wasted_gpu > 0 && with.tasks.wasted_cpu > 0);
+        assert_eq!(be.wasted_gpu, with.tasks.wasted_gpu);
+        // Sampled tasks have no timeline to lose
+        assert_eq!(with.tasks.wasted_time, 0);
+
+        // Without a qos column every task is Burstable, so nothing is preempted
+        let qos_column = pods.lines().next().unwrap().split(',').position(|column| column == "qos" ).unwrap();
+        let unlabeled: String = pods.lines()
+            .map(|line| {
+                let mut fields: Vec<&str> = line.split(',').collect();
+                fields.remove( qos_column );
+                format!("{}\n", fields.join(","))
+            })
+            .collect();
+        let unlabeled = run( &unlabeled, true );
+        assert_eq!(unlabeled.tasks.preemptions, 0);
+        assert_eq!(unlabeled.qos.keys().collect::<Vec<_>>(), [&Qos::Burstable]);
+    }
+
+    #[rstest]
+    fn test_preemption_filters() {
+        let pod_csv =
+        "name,cpu_milli,memory_mib,num_gpu,gpu_milli,gpu_spec,qos
+        be,4000,8192,1,1000,,BE
+        ls,4000,8192,1,1000,,LS";
+        let node_csv =
+        "sn,cpu_milli,memory_mib,gpu,model
+        node-0,96000,262144,1,V100M32";
+
+        // The best-effort task holds the only GPU, so the latency-sensitive one needs it evicted
+        let preempts = |spec: &str| {
+            let scheduler = Box::new( parse_pipeline( spec ).unwrap() );
+            let eval = Evaluator::new( scheduler, stop_after_100(), WorkloadMode::Sample, 0, pod_csv.as_bytes(), node_csv.as_bytes() ).unwrap()
+                .with_preemption( true );
+            let [be, ls] = eval.workload.tasks.clone().try_into().unwrap();
+
+            let node = eval.cluster.nodes[0].clone();
+            let gpu = node.borrow().gpu_rem[0].clone();
+            eval.cluster.bind_task( be.clone(), (node, vec![gpu]) );
+            eval.workload.update_metrics( be, true );
+
+            eval.preempt( &ls ).is_some()
+        };
+
+        // Capacity filters would reject the full node, but evicting makes room
+        assert!(preempts("best_fit+frag_fit"));
+        // Tasks without a model stay off model machines, evictions or not
+        assert!(!preempts("best_fit+frag_fit+exclusive_model"));
+    }
+
+    #[rstest]
+    fn test_replay_drain() {
+        // Three whole-GPU tasks arrive together on a single GPU, and each runs for 10
+        let pod_csv =
+        "name,cpu_milli,memory_mib,num_gpu,gpu_milli,creation_time,deletion_time
+        a,4000,8192,1,1000,0,10
+        b,4000,8192,1,1000,0,10
+        c,4000,8192,1,1000,0,10";
+        let node_csv =
+        "sn,cpu_milli,memory_mib,gpu,model
+        node-0,96000,262144,1,V100M32";
+
+        let scheduler = Box::new( FnScheduler::new( "gpu_packing", gpu_packing_scheduler ));
+        let mut eval = Evaluator::new( scheduler, stop_after_100(), WorkloadMode::Replay, 0, pod_csv.as_bytes(), node_csv.as_bytes() ).unwrap();
+        let batch = eval.run_batch( 0 ).unwrap();
+
+        // Once arrivals run out, delayed tasks wait for departures instead of ending the batch
+        assert_eq!(batch.tasks.tasks_arrived, 3);
+        assert_eq!(batch.tasks.tasks_scheduled, 3);
+        assert_eq!(batch.tasks.queue_delay, 10 + 20);
+    }
+
+    // Scores every node the same, but has no score for the given node
+    struct SkipNode(Option<usize>);
+
+    impl ScorePlugin for SkipNode {
+        fn name(&self) -> &str { "skip_node" }
+
+        fn score(&self, _evaluator: &Evaluator, _task: &PodSpecStruct, _node: &NodeInfoStruct ) -> SCORE { 0 }
+
+        fn feasible(&self, _evaluator: &Evaluator, _task: &PodSpecStruct, node: &NodeInfoStruct ) -> bool {
+            self.0 != Some(node.spec.id)
+        }
+    }
+
+    #[rstest]
+    fn test_pipeline(#[values(WorkloadMode::Sample, WorkloadMode::Replay)] mode: WorkloadMode ) {
+        assert!(parse_pipeline("dot_product*heavy").is_err());
+        assert!(parse_pipeline("best_fit+gpus=none").is_err());
+        assert!(parse_pipeline("best_fit+ties=never").is_err());
+        assert!(parse_pipeline("best_fit+combine=average").is_err());
+        assert!(parse_pipeline("best_fit+shortlist=few").is_err());
+        assert!(parse_pipeline("dot_product*0.7+best_fit*0.3+model_penalty+exclusive_model+gpus=fgd").is_ok());
+
+        let run = |scheduler: BoxedScheduler| {
+            let mut eval = Evaluator::new( scheduler, stop_after_100(), mode, 0, pod_csv(), node_csv() ).unwrap();
+            eval.evaluate( 2, OutputFormat::Json ).unwrap()
+        };
+
+        // The GPU packing plugin with random ties reproduces the GPU packing scheduler
+        let declared = run( Box::new( parse_pipeline("gpu_packing+ties=random").unwrap() ));
+        let built = run( Box::new( Pipeline::new("gpu_packing").score( 1.0, GpuPacking ).ties( Ties::Random ) ));
+        let reference = run( Box::new( FnScheduler::new( "gpu_packing", gpu_packing_scheduler )));
+
+        for ((d, b), r) in declared.iter().zip(&built).zip(&reference) {
+            assert_eq!(d.tasks.tasks_scheduled, r.tasks.tasks_scheduled);
+            assert_eq!(b.tasks.tasks_scheduled, r.tasks.tasks_scheduled);
+            assert_eq!(d.nodes.alloc_rate, r.nodes.alloc_rate);
+            assert_eq!(d.nodes.frag_total, r.nodes.frag_total);
+        }
+
+        // Filters, GPU plugins and tie-breaks compose with it
+        let filtered = run( Box::new(
+            Pipeline::new("filtered").filter( ExclusiveModel ).score( 1.0, GpuPacking ).gpus( FgdGpu )
+        ));
+        assert!(filtered.iter().all(|b| b.tasks.tasks_arrived > 0 ));
+
+        // Tie-breaks only reorder nodes with equal scores, and node ids follow list order
+        let first = run( Box::new( parse_pipeline("gpu_packing").unwrap() ));
+        let by_id = run( Box::new( parse_pipeline("gpu_packing+ties=node_id").unwrap() ));
+        assert_eq!(by_id.iter().map(|b| (b.tasks.tasks_scheduled, b.nodes.frag_total)).collect::<Vec<_>>(),
+                   first.iter().map(|b| (b.tasks.tasks_scheduled, b.nodes.frag_total)).collect::<Vec<_>>());
+
+        // Nodes a score plugin cannot score are dropped, rather than ranked
+        let eval = Evaluator::new( Box::new( parse_pipeline("frag").unwrap() ), stop_after_100(), mode, 0, pod_csv(), node_csv() ).unwrap();
+        let task = eval.workload.tasks[0].clone();
+        let pick = |skip: Option<usize>| {
+            let (node, _) = Pipeline::new("skip").score( 1.0, SkipNode(skip) ).schedule( &eval, task.clone() ).unwrap();
+            node.borrow().spec.id
+        };
+        let first = pick( None );
+        assert_ne!(pick( Some(first) ), first);
+
+        let random = || run( Box::new( parse_pipeline("least_allocated+ties=random").unwrap() ));
+        assert_eq!(random()[1].nodes.alloc_rate, random()[1].nodes.alloc_rate);
+    }
+
+    // Scores each node from a table, by node id
+    struct Table(&'static [SCORE]);
+
+    impl ScorePlugin for Table {
+        fn name(&self) -> &str { "table" }
+
+        fn score(&self, _evaluator: &Evaluator, _task: &PodSpecStruct, node: &NodeInfoStruct ) -> SCORE { self.0[node.spec.id] }
+    }
+
+    #[rstest]
+    fn test_combine() {
+        let pod_csv =
+        "name,cpu_milli,memory_mib,num_gpu,gpu_milli
+        small,4000,8192,1,500";
+        let node_csv: String = std::iter::once("sn,cpu_milli,memory_mib,gpu,model\n".to_string())
+            .chain((0..5).map(|i| format!("node-{},96000,262144,1,V100M32\n", i)))
+            .collect();
+
+        let eval = Evaluator::new( Box::new( parse_pipeline("best_fit").unwrap() ), stop_after_100(), WorkloadMode::Sample, 0,
+                                   pod_csv.as_bytes(), node_csv.as_bytes() ).unwrap();
+        let task = eval.workload.tasks[0].clone();
+
+        // A small-scale and a large-scale score. Node 0 is beaten by node 2 on both,
+        // and without it the first score's range shrinks
+        const SMALL: &[SCORE] = &[0, 10, 7, 8, 3];
+        const LARGE: &[SCORE] = &[4, 3, 9000, 8000, 10000];
+
+        let pick = |combine: Combine, small: WEIGHT| {
+            let mut pipeline = Pipeline::new("table").score( small, Table(SMALL) ).score( 1.0, Table(LARGE) ).combine( combine );
+            let (node, _) = pipeline.schedule( &eval, task.clone() ).unwrap();
+            node.borrow().spec.id
+        };
+
+        // Raw sums follow the large score, lexicographic order the first one
+        assert_eq!(pick( Combine::Weighted, 1.0 ), 4);
+        assert_eq!(pick( Combine::Lexicographic, 1.0 ), 1);
+        assert_eq!(pick( Combine::Normalized, 1.0 ), 2);
+        assert_eq!(pick( Combine::Pareto, 1.0 ), 3);
+
+        // Negative weights minimize, which lexicographic order only takes the direction from
+        assert_eq!(pick( Combine::Lexicographic, -0.5 ), 0);
+
+        assert_eq!(parse_pipeline("best_fit+combine=pareto").unwrap().combine, Combine::Pareto);
+
+        // A shortlist of the best nodes by the first score, which the rest then rank. Without it, node 2 wins
+        let pick = |shortlist: Option<usize>| {
+            let pipeline = Pipeline::new("table").score( 1.0, Table(SMALL) ).score( 2.0, Table(LARGE) );
+            let mut pipeline = match shortlist { Some(k) => pipeline.shortlist( k ), None => pipeline };
+            let (node, _) = pipeline.schedule( &eval, task.clone() ).unwrap();
+            node.borrow().spec.id
+        };
+        assert_eq!(pick( None ), 2);
+        assert_eq!(pick( Some(2) ), 3);
+        assert_eq!(pick( Some(1) ), 1);
+        assert_eq!(parse_pipeline("best_fit+shortlist=10").unwrap().shortlist, Some(10));
+    }
+}
@@ -0,0 +1,213 @@
+use crate::evaluator::cluster::NodeMetrics;
+use crate::evaluator::workload::{QosBreakdown, TaskMetrics};
+use crate::types::*;
+use serde::Serialize;
+use crate::error::{self, write_error, Error, Result};
+use std::path::Path;
+
+// Identifies a run, plus metrics averaged over all of its batches
+#[derive(Debug, Clone)]
+#[derive(Default)]
+#[derive(Serialize)]
+#[public]
+struct Summary {
+    scheduler: String,
+    decider: String,
+    trace: String,
+    nodes: String,
+    seed: SEED,
+
+    // Why the run could not be made, e.g. a trace without a timeline to replay. None if it ran
+    skipped: Option<String>,
+
+    batches: usize,
+    avg_tasks_scheduled: f64,
+    avg_alloc_rate: f64,
+    avg_frag_rate: f64,
+}
+
+// Row number for CSV rows, which cannot nest structs
+#[derive(Serialize)]
+struct BatchNum {
+    batch: usize,
+}
+
+// QoS class column for qos.csv rows
+#[derive(Serialize)]
+struct QosClass {
+    qos: Qos,
+}
+
+#[derive(Debug, Clone)]
+#[derive(Serialize)]
+#[public]
+struct BatchRecord {
+    batch: usize,
+    tasks: TaskMetrics,
+    qos: QosBreakdown,
+    nodes: NodeMetrics,
+}
+
+#[derive(Debug, Clone)]
+#[derive(Serialize)]
+#[public]
+struct Report {
+    summary: Summary,
+    batches: Vec<BatchRecord>,
+}
+
+impl Report {
+
+    // Fills in the summary averages from the batches
+    pub fn new( mut summary: Summary, batches: Vec<BatchRecord> ) -> Self {
+        let n = batches.len().max(1) as f64;
+
+        let tasks_scheduled: f64 = batches.iter().map(|b| b.tasks.tasks_scheduled as f64 ).sum();
+        let gpu_unallocated: f64 = batches.iter().map(|b| b.nodes.gpu_unallocated as f64 ).sum();
+        let frag_total: f64 = batches.iter().map(|b| b.nodes.frag_total as f64 ).sum();
+        let gpu_total: f64 = batches.iter().map(|b| b.nodes.gpu_total as f64 ).sum();
+
+        summary.batches = batches.len();
+        summary.avg_tasks_scheduled = tasks_scheduled / n;
+        summary.avg_alloc_rate = if gpu_total == 0.0 { 0.0 } else { 1.0 - gpu_unallocated / gpu_total };
+        summary.avg_frag_rate = if gpu_unallocated == 0.0 { 0.0 } else { frag_total / gpu_unallocated };
+
+        Self { summary, batches }
+    }
+
+    // Task metrics per QoS class, summed over all batches
+    pub fn qos_totals(&self) -> QosBreakdown {
+        let mut totals = QosBreakdown::new();
+
+        for (&qos, m) in self.batches.iter().flat_map(|b| &b.qos) {
+            totals.entry(qos).or_default().add(m);
+        }
+
+        totals
+    }
+
+    // batches.csv (one row per batch), qos.csv (one row per batch and QoS class) and summary.csv (one row)
+    pub fn write_csv( &self, dir: &Path ) -> Result<()> {
+        let mut wtr = csv::Writer::from_writer( error::create( dir.join("batches.csv") )? );
+        self.batches.iter().try_for_each(|b| {
+            wtr.serialize(( BatchNum { batch: b.batch }, &b.tasks, &b.nodes ))
+        }).map_err(write_error)?;
+        wtr.flush().map_err(Error::Write)?;
+
+        let mut wtr = csv::Writer::from_writer( error::create( dir.join("qos.csv") )? );
+        self.batches.iter().try_for_each(|b| {
+            b.qos.iter().try_for_each(|(&qos, m)| {
+                wtr.serialize(( BatchNum { batch: b.batch }, QosClass { qos }, m ))
+            })
+        }).map_err(write_error)?;
+        wtr.flush().map_err(Error::Write)?;
+
+        let mut wtr = csv::Writer::from_writer( error::create( dir.join("summary.csv") )? );
+        wtr.serialize( &self.summary ).map_err(write_error)?;
+        wtr.flush().map_err(Error::Write)
+    }
+
+    // report.json, with the summary and every batch
+    pub fn write_json( &self, dir: &Path ) -> Result<()> {
+        let file = error::create( dir.join("report.json") )?;
+        serde_json::to_writer_pretty( file, self ).map_err(write_error)
+    }
+}
+
+impl std::fmt::Display for Summary {
+
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "Scheduler: {}, decider: {}, trace: {}, nodes: {}, seed: {}",
+                 self.scheduler, self.decider, self.trace, self.nodes, self.seed )?;
+
+        writeln!(f, "Average tasks scheduled: {:.0}", self.avg_tasks_scheduled )?;
+        writeln!(f, "Average allocation rate : {:.2}", self.avg_alloc_rate * 100.0)?;
+        writeln!(f, "Average fragmentation rate : {:.2}", self.avg_frag_rate * 100.0)
+    }
+}
+
+impl std::fmt::Display for Report {
+
+    // Summary, then how each QoS class fared, so starved classes stand out
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.summary)?;
+
+        let n = self.batches.len().max(1) as f64;
+        self.qos_totals().iter().try_for_each(|(qos, m)| {
+            writeln!(f, "{: <10} arrived: {: >8.1}, scheduled: {: >8.1}, delayed: {: >8.1} per batch, {: >6.2}% scheduled",
+                     qos.to_string(),
+                     m.tasks_arrived as f64 / n,
+                     m.tasks_scheduled as f64 / n,
+                     m.tasks_delayed as f64 / n,
+                     m.tasks_scheduled as f64 * 100.0 / (m.tasks_scheduled + m.tasks_delayed).max(1) as f64 )
+        })?;
+
+        let preempted: TaskMetrics = self.batches.iter().fold(TaskMetrics::default(), |mut total, b| {
+            total.add( &b.tasks );
+            total
+        });
+        if preempted.preemptions > 0 {
+            writeln!(f, "Preemptions: {: >8.1}, tasks evicted: {: >8.1}, wasted GPU: {: >8.1}, CPU: {: >10.1}, time: {: >10.1} s per batch",
+                     preempted.preemptions as f64 / n,
+                     preempted.tasks_preempted as f64 / n,
+                     preempted.wasted_gpu as f64 / n,
+                     preempted.wasted_cpu as f64 / n,
+                     preempted.wasted_time as f64 / n )?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn test_write() {
+        let batches = (1..=3).map(|batch| BatchRecord {
+            batch,
+            tasks: TaskMetrics { tasks_scheduled: batch * 10, ..Default::default() },
+            qos: QosBreakdown::from([
+                (Qos::LS, TaskMetrics { tasks_scheduled: batch * 4, ..Default::default() }),
+                (Qos::BE, TaskMetrics { tasks_scheduled: batch * 6, ..Default::default() }),
+            ]),
+            nodes: NodeMetrics { gpu_total: 1000, gpu_unallocated: 500, frag_total: 100, ..Default::default() },
+        }).collect();
+
+        let summary = Summary { scheduler: String::from("best_fit"), seed: 7, ..Default::default() };
+        let report = Report::new( summary, batches );
+
+        assert_eq!(report.summary.batches, 3);
+        assert_eq!(report.summary.avg_tasks_scheduled, 20.0);
+        assert_eq!(report.summary.avg_alloc_rate, 0.5);
+        assert_eq!(report.summary.avg_frag_rate, 0.2);
+        assert_eq!(report.qos_totals()[&Qos::LS].tasks_scheduled, 24);
+
+        // Own directory per process, so concurrent test runs do not share files
+        let dir = std::env::temp_dir().join(format!("gpu_scheduler_sim_{}_test_report", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+
+        report.write_csv(&dir).unwrap();
+        report.write_json(&dir).unwrap();
+
+        let batches_csv = fs::read_to_string(dir.join("batches.csv")).unwrap();
+        assert!(batches_csv.starts_with("batch,tasks_arrived,"));
+        assert_eq!(batches_csv.lines().count(), 4);
+
+        let qos_csv = fs::read_to_string(dir.join("qos.csv")).unwrap();
+        assert!(qos_csv.starts_with("batch,qos,tasks_arrived,"));
+        assert!(qos_csv.lines().nth(1).unwrap().starts_with("1,LS,"));
+        assert_eq!(qos_csv.lines().count(), 7);
+
+        let json: serde_json::Value = serde_json::from_str(
+            &fs::read_to_string(dir.join("report.json")).unwrap()
+        ).unwrap();
+        assert_eq!(json["summary"]["scheduler"], "best_fit");
+        assert_eq!(json["batches"][2]["tasks"]["tasks_scheduled"], 30);
+        assert_eq!(json["batches"][2]["qos"]["BE"]["tasks_scheduled"], 18);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
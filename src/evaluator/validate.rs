@@ -0,0 +1,265 @@
+use crate::evaluator::*;
+use crate::mig::Slices;
+use std::rc::Rc;
+
+// Strict mode: picks are checked before they are bound, and the bound node's aggregates
+// are re-derived from its GPUs and tasks afterwards, so a buggy scheduler fails loudly
+// instead of silently corrupting the cluster
+
+// First broken invariant of a run, and the task that broke it
+#[derive(Debug, Clone)]
+#[public]
+struct Violation {
+    task: PodSpec,
+    reason: String,
+}
+
+impl std::fmt::Display for Violation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "invalid placement of pod {}: {}", self.task, self.reason )
+    }
+}
+
+impl std::error::Error for Violation {}
+
+impl ClusterStruct {
+
+    // Whether the pick can be bound: a node of this cluster with room for the task,
+    // and exactly `num_gpu` distinct GPUs of that node with enough left
+    pub fn validate_pick(&self, task: &PodSpecStruct, (node_ref, gpu_vec): &SchedulingPick ) -> Result<(), String> {
+        let node = node_ref.borrow();
+        let name = &node.spec.name;
+
+        if !self.nodes.get(node.spec.id).is_some_and(|own| Rc::ptr_eq( own, node_ref )) {
+            return Err(format!("node {} is not part of the cluster", name));
+        }
+
+        if task.cpu_milli > node.cpu_rem {
+            return Err(format!("node {} has {} cpu milli left, task needs {}", name, node.cpu_rem, task.cpu_milli));
+        }
+        if task.memory_mib > node.mem_rem {
+            return Err(format!("node {} has {} MiB left, task needs {}", name, node.mem_rem, task.memory_mib));
+        }
+        if !task.model.is_empty() && !task.model.intersects( node.spec.model.clone() ) {
+            return Err(format!("node {} has {} GPUs, task needs {}", name, node.spec.model, task.model));
+        }
+
+        if gpu_vec.len() != task.num_gpu {
+            return Err(format!("picked {} GPUs, task needs {}", gpu_vec.len(), task.num_gpu));
+        }
+
+        for (i, gpu_ref) in gpu_vec.iter().enumerate() {
+            let gpu = gpu_ref.borrow();
+
+            if !node.gpu_rem.get(gpu.id).is_some_and(|own| Rc::ptr_eq( own, gpu_ref )) {
+                return Err(format!("GPU {} does not belong to node {}", gpu.id, name));
+            }
+            if gpu_vec[..i].iter().any(|other| Rc::ptr_eq( other, gpu_ref )) {
+                return Err(format!("GPU {} of node {} picked twice", gpu.id, name));
+            }
+            if gpu.usable() < task.per_gpu_milli() {
+                return Err(format!("GPU {} of node {} has {} milli left, task needs {}",
+                                   gpu.id, name, gpu.usable(), task.per_gpu_milli()));
+            }
+            if gpu.gpu_memory_mib.is_some_and(|gpu_memory_mib| gpu_memory_mib < task.gpu_memory_mib ) {
+                return Err(format!("GPU {} of node {} has {} MiB of GPU memory left, task needs {}",
+                                   gpu.id, name, gpu.gpu_memory_mib.unwrap_or_default(), task.gpu_memory_mib));
+            }
+            if gpu.mig.is_some() && gpu.slot( task ).is_none() {
+                return Err(format!("GPU {} of node {} has no free MIG instance for {} milli and {} MiB of GPU memory",
+                                   gpu.id, name, task.per_gpu_milli(), task.gpu_memory_mib));
+            }
+        }
+
+        Ok(())
+    }
+
+    // Whether the placement registry reports the binding just made, on the node and GPUs that were picked
+    pub fn check_placement(&self, task: &PodSpecStruct, bind: usize, (node_ref, gpu_vec): &SchedulingPick ) -> Result<(), String> {
+        let placement = self.placement( task.id )
+            .ok_or_else(|| format!("bind {} is missing from the placement registry", bind))?;
+
+        let node = node_ref.borrow();
+        let gpus: Vec<NUM> = gpu_vec.iter().map(|gpu| gpu.borrow().id ).collect();
+
+        if placement.bind != bind || placement.node != node.spec.id || placement.gpus != gpus {
+            return Err(format!("placement registry has {}, bind {} went to node {} GPUs {:?}",
+                               placement, bind, node.spec.name, gpus));
+        }
+        if !node.pods.iter().any(|p| p.bind == bind ) {
+            return Err(format!("node {} does not list bind {}", node.spec.name, bind));
+        }
+
+        Ok(())
+    }
+
+    // Re-derive the node's aggregates from its GPUs and bound tasks, then the cluster totals from its nodes
+    pub fn check_aggregates(&self, node_ref: &NodeInfo ) -> Result<(), String> {
+        self.check_node( &node_ref.borrow() )?;
+
+        let metrics = self.metrics.borrow();
+        let (gpu_unallocated, frag_total) = self.nodes.iter()
+            .map(|node| {
+                let node = node.borrow();
+                (node.gpu_unallocated, node.gpu_frag)
+            })
+            .fold((0, 0), |(a, f), (node_a, node_f)| (a + node_a, f + node_f));
+
+        if (metrics.gpu_unallocated, metrics.frag_total) != (gpu_unallocated, frag_total) {
+            return Err(format!("cluster (unallocated, frag) is {:?}, its nodes give {:?}",
+                               (metrics.gpu_unallocated, metrics.frag_total), (gpu_unallocated, frag_total)));
+        }
+
+        Ok(())
+    }
+
+    fn check_node(&self, node: &NodeInfoStruct ) -> Result<(), String> {
+        let name = &node.spec.name;
+        let residents = self.residents( node );
+
+        let cpu: CPU = residents.iter().map(|b| b.task.cpu_milli ).sum();
+        let mem: MEM = residents.iter().map(|b| b.task.memory_mib ).sum();
+        if node.cpu_rem + cpu != node.spec.cpu_milli {
+            return Err(format!("node {} has {} cpu milli left, its tasks use {} of {}", name, node.cpu_rem, cpu, node.spec.cpu_milli));
+        }
+        if node.mem_rem + mem != node.spec.memory_mib {
+            return Err(format!("node {} has {} MiB left, its tasks use {} of {}", name, node.mem_rem, mem, node.spec.memory_mib));
+        }
+
+        for gpu in node.gpu_rem.iter() {
+            let gpu = gpu.borrow();
+            let used: GPU = node.pods.iter()
+                .filter(|p| p.gpus.contains(&gpu.id) )
+                .map(|p| p.gpu_milli )
+                .sum();
+
+            if gpu.gpu_milli + used != GPU_MILLI {
+                return Err(format!("GPU {} of node {} has {} milli left, its tasks use {}", gpu.id, name, gpu.gpu_milli, used));
+            }
+
+            if let Some(gpu_memory_mib) = gpu.gpu_memory_mib {
+                let used: MEM = node.pods.iter()
+                    .filter(|p| p.gpus.contains(&gpu.id) )
+                    .map(|p| p.gpu_memory_mib )
+                    .sum();
+
+                if Some(gpu_memory_mib + used) != node.spec.model.gpu_memory_mib() {
+                    return Err(format!("GPU {} of node {} has {} MiB of GPU memory left, its tasks use {}", gpu.id, name, gpu_memory_mib, used));
+                }
+            }
+
+            // MIG slices in use are exactly the instances of its tasks, whole GPUs for multi-GPU tasks
+            if let Some(slices) = gpu.mig {
+                let expected = node.pods.iter()
+                    .filter(|p| p.gpus.contains(&gpu.id) )
+                    .try_fold(Slices::default(), |used, p| match p.slot {
+                        Some(slot) => Ok(used.claim( slot )),
+                        None if p.gpus.len() > 1 => Ok(Slices::FULL),
+                        None => Err(format!("pod {} holds a share of GPU {} of node {} without a MIG instance", p.pod_name, gpu.id, name)),
+                    })?;
+
+                if slices != expected {
+                    return Err(format!("GPU {} of node {} has MIG slices {} in use, its tasks hold {}", gpu.id, name, slices, expected));
+                }
+            }
+        }
+
+        let state = node.frag_state();
+        let gpu_unallocated: GPU = state.gpu_rem.iter().sum();
+        let gpu_full = state.gpu_rem.iter().filter(|&&gpu_milli| gpu_milli == GPU_MILLI ).count();
+        let gpu_part = (0..state.gpu_rem.len())
+            .filter(|&id| state.gpu_rem[id] < GPU_MILLI )
+            .map(|id| state.usable(id) )
+            .max().unwrap_or(0);
+        let gpu_frag = state.node_frag( &self.workload );
+
+        if (node.gpu_unallocated, node.gpu_full, node.gpu_part, node.gpu_frag) != (gpu_unallocated, gpu_full, gpu_part, gpu_frag) {
+            return Err(format!("node {} aggregates (unallocated, full, part, frag) are {:?}, its GPUs give {:?}", name,
+                               (node.gpu_unallocated, node.gpu_full, node.gpu_part, node.gpu_frag),
+                               (gpu_unallocated, gpu_full, gpu_part, gpu_frag)));
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::heuristics::parse_decider;
+    use crate::heuristics::simple_schedulers::gpu_packing_scheduler;
+    use rstest::rstest;
+    use std::fs::File;
+
+    fn evaluator( scheduler: BoxedScheduler ) -> Evaluator {
+        let pod_csv = File::open("clusterdata/pod_data/default.csv").expect("pod file not found");
+        let node_csv = File::open("clusterdata/node_data/gpu_nodes.csv").expect("node file not found");
+
+        Evaluator::new( scheduler, parse_decider("max_tasks_arrived=100").unwrap(), WorkloadMode::Sample, 0, pod_csv, node_csv ).unwrap()
+            .with_strict( true )
+    }
+
+    #[rstest]
+    fn test_valid_scheduler() {
+        let mut eval = evaluator( Box::new( FnScheduler::new( "gpu_packing", gpu_packing_scheduler )));
+
+        assert!(eval.evaluate( 2, OutputFormat::Json ).is_ok());
+    }
+
+    #[rstest]
+    #[case::foreign_gpu("does not belong to node")]
+    #[case::gpu_twice("picked twice")]
+    #[case::too_few_gpus("GPUs, task needs")]
+    #[case::full_gpu("milli left, task needs")]
+    fn test_invalid_pick( #[case] expected: &'static str ) {
+        // Delegates to GPU packing, then breaks the pick of the first GPU task
+        let scheduler = FnScheduler::new( "buggy", move |evaluator: &Evaluator, task: PodSpec| {
+            let (node, mut gpus) = gpu_packing_scheduler( evaluator, task.clone() )?;
+            if task.num_gpu == 0 { return Some((node, gpus)); }
+
+            let other = &evaluator.cluster.nodes[ (node.borrow().spec.id + 1) % evaluator.cluster.nodes.len() ];
+            match expected {
+                "does not belong to node" => gpus[0] = other.borrow().gpu_rem[0].clone(),
+                "picked twice" if gpus.len() > 1 => gpus[1] = gpus[0].clone(),
+                "picked twice" => {},
+                "GPUs, task needs" => { gpus.pop(); },
+                _ => gpus[0].borrow_mut().gpu_milli = 0,
+            }
+
+            Some((node, gpus))
+        });
+
+        let Err(Error::Violation(violation)) = evaluator( Box::new(scheduler) ).run_batch( 0 ) else {
+            panic!("expected a violation");
+        };
+
+        // The first GPU task is the one reported
+        assert!(violation.task.num_gpu > 0);
+        assert!(violation.reason.contains( expected ), "{}", violation);
+    }
+
+    #[rstest]
+    fn test_aggregates() {
+        let eval = evaluator( Box::new( FnScheduler::new( "gpu_packing", gpu_packing_scheduler )));
+        let cluster = &eval.cluster;
+
+        let task = eval.workload.tasks.iter().find(|task| task.single_gpu() ).unwrap().clone();
+        let pick = gpu_packing_scheduler( &eval, task.clone() ).unwrap();
+        assert!(cluster.validate_pick( &task, &pick ).is_ok());
+
+        cluster.bind_task( task, pick.clone() );
+        assert!(cluster.check_aggregates( &pick.0 ).is_ok());
+
+        // Resources that appeared without a task releasing them
+        pick.0.borrow_mut().cpu_rem += 1;
+        assert!(cluster.check_aggregates( &pick.0 ).unwrap_err().contains("cpu milli left"));
+        pick.0.borrow_mut().cpu_rem -= 1;
+
+        pick.1[0].borrow_mut().gpu_milli += 1;
+        assert!(cluster.check_aggregates( &pick.0 ).unwrap_err().contains("milli left, its tasks use"));
+        pick.1[0].borrow_mut().gpu_milli -= 1;
+
+        cluster.metrics.borrow_mut().frag_total += 1;
+        assert!(cluster.check_aggregates( &pick.0 ).unwrap_err().starts_with("cluster"));
+    }
+}
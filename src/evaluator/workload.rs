@@ -1,19 +1,41 @@
 use super::*;
 use rand::prelude::*;
+use rand::rngs::StdRng;
 use std::cell::RefCell;
-use std::collections::{HashMap, VecDeque};
+use std::collections::{BTreeMap, HashMap, VecDeque};
 use std::io::Read;
 use std::rc::Rc;
 
 type TaskCount = HashMap<PodSpecKey, usize>;
 
+// Task metrics for each QoS class that showed up in the batch
+pub type QosBreakdown = BTreeMap<Qos, TaskMetrics>;
+
+// How tasks arrive
+#[derive(Debug, Clone, Copy)]
+#[derive(PartialEq, Eq)]
+#[derive(Default)]
+pub enum WorkloadMode {
+    // Sample tasks uniformly at random, forever
+    #[default]
+    Sample,
+    // Replay the trace in creation order, releasing tasks at deletion time
+    Replay,
+}
+
 #[derive(Debug, Clone)]
 #[public]
 struct WorkloadStruct {
 
     name : String,
+    mode: WorkloadMode,
+
+    rng: RefCell<StdRng>,
 
-    rng: RefCell<ThreadRng>,
+    // Trace replay: current time and position in the creation-ordered trace
+    clock: RefCell<TIME>,
+    next_arrival: RefCell<POD>,
+    arrivals: Vec<PodSpec>,
 
     drain_backlog: RefCell<usize>,
     backlog: RefCell<VecDeque<PodSpec>>,
@@ -22,13 +44,15 @@ struct WorkloadStruct {
     tasks: Vec<PodSpec>,
     task_count: TaskCount,
 
-    metrics: RefCell<TaskMetrics>
+    metrics: RefCell<TaskMetrics>,
+    qos_metrics: RefCell<QosBreakdown>,
 }
 pub type Workload = Rc<WorkloadStruct>;
 
 
 #[derive(Debug, Clone)]
 #[derive(Default)]
+#[derive(serde::Serialize)]
 #[public]
 struct TaskMetrics {
     tasks_arrived: POD,
@@ -38,17 +62,29 @@ struct TaskMetrics {
     total_cpu: CPU,
     total_mem: MEM,
     total_gpu: GPU,
+
+    // Trace replay: time scheduled tasks spent waiting since creation
+    queue_delay: TIME,
+
+    // Preemption: tasks that got in by evicting others, and the tasks evicted back into the backlog.
+    // Evicted tasks no longer count as scheduled, until they are bound again
+    preemptions: POD,
+    tasks_preempted: POD,
+    // Resources evicted tasks held, and in trace replay how long they had already run. Always 0 in Sample mode
+    wasted_cpu: CPU,
+    wasted_gpu: GPU,
+    wasted_time: TIME,
 }
 
 impl WorkloadStruct {
-    pub fn new( name: String, pod_csv : impl Read )  -> Self {
+    pub fn new( name: String, mode: WorkloadMode, seed: SEED, pod_csv : impl Read )  -> Result<Self> {
 
         let drain_backlog = RefCell::new(0);
         let backlog = RefCell::new(VecDeque::new());
         let mut task_count = HashMap::new();
         let mut tasks = Vec::new();
 
-        process_csv(pod_csv, |i, mut record: PodSpecStruct | {
+        process_input(pod_csv, |i, mut record: PodSpecStruct | {
 
             // Pre-process PodSpec so that gpu_milli can be used directly,
             // Assuming GPUs are always allocated as one fraction or an integer number.
@@ -59,27 +95,48 @@ impl WorkloadStruct {
             let task = {
                 let mut record = record.clone();
                 record.id = i;
+                if record.name.is_empty() {
+                    record.name = Name::new( &format!("pod-{}", i) );
+                }
                 Rc::new(record)
             };
 
             tasks.push(task);
-            *task_count.entry(record).or_insert(0) += 1;
+            *task_count.entry(record.key()).or_insert(0) += 1;
 
             Ok(())
 
-        }).expect("Failed to process Workload CSV");
+        }).map_err(|err| err.in_input( &name ))?;
+
+        if tasks.is_empty() {
+            return Err(Error::Empty { input: name });
+        }
+
+        // Without a timeline every task would arrive at 0 and never leave
+        if mode == WorkloadMode::Replay && tasks.iter().all(|task| task.deletion_time == 0) {
+            return Err(Error::NoTimeline { input: name });
+        }
 
         let num_tasks = tasks.len();
-        let rng = RefCell::new(rand::rng());
+        let rng = RefCell::new(StdRng::seed_from_u64(seed));
         let metrics = RefCell::new(TaskMetrics::default());
+        let qos_metrics = RefCell::new(QosBreakdown::new());
+
+        let mut arrivals = tasks.clone();
+        arrivals.sort_by_key(|task| task.creation_time);
+
+        let clock = RefCell::new(0);
+        let next_arrival = RefCell::new(0);
 
-        Self {
+        Ok(Self {
             name,
+            mode,
             rng,
+            clock, next_arrival, arrivals,
             drain_backlog, backlog,
             num_tasks, tasks, task_count,
-            metrics
-        }
+            metrics, qos_metrics,
+        })
     }
 
     pub fn next_task(&self) -> PodSpec {
@@ -87,16 +144,56 @@ impl WorkloadStruct {
             return m;
         }
 
-        // Select random task
+        let task = match self.mode {
+            // Select random task
+            WorkloadMode::Sample => {
+                let task = self.tasks.choose(&mut self.rng.borrow_mut()).unwrap();
+                task.to_owned()
+            },
+            // Select next task to be created, and advance the clock to it
+            WorkloadMode::Replay => {
+                let mut next_arrival = self.next_arrival.borrow_mut();
+
+                let task = self.arrivals[*next_arrival].to_owned();
+                *next_arrival += 1;
+                *self.clock.borrow_mut() = task.creation_time;
+
+                task
+            },
+        };
+
         self.metrics.borrow_mut().tasks_arrived += 1;
+        self.qos_metrics.borrow_mut().entry(task.qos).or_default().tasks_arrived += 1;
+
+        task
+    }
 
-        let task = self.tasks.choose(&mut self.rng.borrow_mut()).unwrap();
-        task.to_owned()
+    pub fn now(&self) -> TIME { *self.clock.borrow() }
+
+    // Trace replay: let time pass without a new arrival
+    pub fn advance_to(&self, time: TIME ) {
+        let mut clock = self.clock.borrow_mut();
+        *clock = (*clock).max(time);
+    }
+
+    // Trace replay has run out of tasks, both new and delayed
+    pub fn exhausted(&self) -> bool {
+        self.mode == WorkloadMode::Replay &&
+            self.drain_backlog() == 0 &&
+            *self.next_arrival.borrow() >= self.num_tasks
+    }
+
+    // Trace replay: the task keeps its trace duration, counted from when it is scheduled now
+    pub fn departure_time(&self, task: &PodSpec ) -> Option<TIME> {
+        match self.mode {
+            WorkloadMode::Sample => None,
+            WorkloadMode::Replay => Some(self.now() + task.duration()),
+        }
     }
 
     #[allow(unused)]
     pub fn task_count( &self, task : &PodSpec ) -> usize {
-        let &count = self.task_count.get(task).unwrap_or(&0);
+        let &count = self.task_count.get(&task.key()).unwrap_or(&0);
         count
     }
 
@@ -114,8 +211,10 @@ impl WorkloadStruct {
         task_opt
     }
 
-    #[allow(unused)]
     pub fn inc_backlog_drain(&self ) { *self.drain_backlog.borrow_mut() += 1 }
+
+    // Resources were freed, so give every delayed task another chance
+    pub fn retry_backlog(&self) { *self.drain_backlog.borrow_mut() = self.backlog_size() }
     pub fn dec_backlog_drain( &self ) {  *self.drain_backlog.borrow_mut() -= 1 }
 
     pub fn drain_backlog(&self) -> usize { *self.drain_backlog.borrow() }
@@ -125,31 +224,106 @@ impl WorkloadStruct {
         self.backlog.borrow().len()
     }
 
-    pub fn deploy(&self) -> TaskMetrics {
+    pub fn deploy(&self) -> (TaskMetrics, QosBreakdown) {
+        // Trace replay starts over on an empty cluster, so delayed tasks will arrive again
+        if self.mode == WorkloadMode::Replay {
+            self.backlog.borrow_mut().clear();
+            *self.next_arrival.borrow_mut() = 0;
+            *self.clock.borrow_mut() = 0;
+        }
+
         // Reset backlog queue for draining again
         *self.drain_backlog.borrow_mut() = self.backlog_size();
 
-        let metrics = self.metrics.borrow().clone();
-        *self.metrics.borrow_mut() = TaskMetrics::default();
+        let metrics = self.metrics.take();
+        let qos_metrics = self.qos_metrics.take();
 
-        metrics
+        (metrics, qos_metrics)
     }
 
     pub fn update_metrics(&self, task: PodSpec, scheduled: bool ) {
-        let mut metrics = self.metrics.borrow_mut();
+        let queue_delay = match self.mode {
+            WorkloadMode::Sample => 0,
+            WorkloadMode::Replay => self.now().saturating_sub(task.creation_time),
+        };
+
+        self.metrics.borrow_mut().record(&task, scheduled, queue_delay);
+        self.qos_metrics.borrow_mut().entry(task.qos).or_default().record(&task, scheduled, queue_delay);
+    }
+
+    // The task will be bound in place of evicted ones
+    pub fn record_preemption(&self, task: &PodSpec ) {
+        self.metrics.borrow_mut().preemptions += 1;
+        self.qos_metrics.borrow_mut().entry(task.qos).or_default().preemptions += 1;
+    }
+
+    // Back to the backlog, losing the work it did since it was bound at `bound_at`
+    pub fn evict(&self, task: PodSpec, bound_at: TIME ) {
+        // Trace replay: the queue delay recorded when it was bound, and the time it has run since
+        let (queue_delay, wasted_time) = match self.mode {
+            WorkloadMode::Sample => (0, 0),
+            WorkloadMode::Replay => (bound_at.saturating_sub(task.creation_time), self.now().saturating_sub(bound_at)),
+        };
+
+        self.metrics.borrow_mut().evict(&task, queue_delay, wasted_time);
+        self.qos_metrics.borrow_mut().entry(task.qos).or_default().evict(&task, queue_delay, wasted_time);
+
+        // Retried right away, ahead of the tasks already delayed this batch
+        self.backlog.borrow_mut().push_front(task);
+        self.inc_backlog_drain();
+    }
+}
+
+impl TaskMetrics {
+    // Sum of both, e.g. over batches
+    pub fn add(&mut self, other: &TaskMetrics ) {
+        self.tasks_arrived += other.tasks_arrived;
+        self.tasks_scheduled += other.tasks_scheduled;
+        self.tasks_delayed += other.tasks_delayed;
+
+        self.total_cpu += other.total_cpu;
+        self.total_mem += other.total_mem;
+        self.total_gpu += other.total_gpu;
+
+        self.queue_delay += other.queue_delay;
+
+        self.preemptions += other.preemptions;
+        self.tasks_preempted += other.tasks_preempted;
+        self.wasted_cpu += other.wasted_cpu;
+        self.wasted_gpu += other.wasted_gpu;
+        self.wasted_time += other.wasted_time;
+    }
 
+    fn record(&mut self, task: &PodSpecStruct, scheduled: bool, queue_delay: TIME ) {
         if !scheduled {
-            metrics.tasks_delayed += 1;
+            self.tasks_delayed += 1;
 
         } else {
-            metrics.tasks_scheduled +=1;
+            self.tasks_scheduled +=1;
 
-            metrics.total_cpu += task.cpu_milli;
-            metrics.total_mem += task.memory_mib;
-            metrics.total_gpu += task.gpu_milli;
+            self.total_cpu += task.cpu_milli;
+            self.total_mem += task.memory_mib;
+            self.total_gpu += task.gpu_milli;
+
+            self.queue_delay += queue_delay;
         }
+    }
+
+    // Takes back what `record` counted when the task was bound, so a task bound again counts once.
+    // Clusters start every batch empty, so the task was bound in this batch
+    fn evict(&mut self, task: &PodSpecStruct, queue_delay: TIME, wasted_time: TIME ) {
+        self.tasks_scheduled -= 1;
 
+        self.total_cpu -= task.cpu_milli;
+        self.total_mem -= task.memory_mib;
+        self.total_gpu -= task.gpu_milli;
 
+        self.queue_delay -= queue_delay;
+
+        self.tasks_preempted += 1;
+        self.wasted_cpu += task.cpu_milli;
+        self.wasted_gpu += task.gpu_milli;
+        self.wasted_time += wasted_time;
     }
 }
 
@@ -188,10 +362,16 @@ impl std::fmt::Display for TaskMetrics {
         writeln!(f, "Tasks arrived: {} = {}(scheduled) + {}(delayed)",
                  self.tasks_arrived, self.tasks_scheduled, self.tasks_delayed )?;
 
-        write!(f, "Total resources consumed: {: >4.1} cpu\t{: >4.1} GiB\t{: >4.1} GPU",
+        writeln!(f, "Total resources consumed: {: >4.1} cpu\t{: >4.1} GiB\t{: >4.1} GPU",
                self.total_cpu as f64 / CPU_MILLI as f64,
                self.total_mem as f64 / MEM_MIB as f64,
-               self.total_gpu as f64 / GPU_MILLI as f64 )
+               self.total_gpu as f64 / GPU_MILLI as f64 )?;
+
+        writeln!(f, "Average queueing delay: {:.1} s",
+               self.queue_delay as f64 / self.tasks_scheduled.max(1) as f64 )?;
+
+        write!(f, "Preemptions: {}, tasks evicted: {}, wasted time: {} s",
+               self.preemptions, self.tasks_preempted, self.wasted_time )
 
     }
 }
@@ -221,20 +401,40 @@ mod tests {
 
         let file_path = prefix.to_owned() + file_name;
         let file = File::open(&file_path)
-            .expect( format!("{} file not found", file_path ).as_str() );
+            .unwrap_or_else(|_| panic!("{} file not found", file_path));
 
-        let workload = WorkloadStruct::new(file_path, file);
+        let workload = WorkloadStruct::new(file_path, WorkloadMode::Sample, 0, file).unwrap();
 
         println!("{}", workload);
     }
 
+    #[rstest]
+    fn test_names() {
+        let csv = "name,cpu_milli,memory_mib,num_gpu,gpu_milli
+        openb-pod-0017,88000,327680,8,1000
+        ,4000,15258,1,50";
+
+        let workload = WorkloadStruct::new(String::from("workload"), WorkloadMode::Sample, 0, csv.as_bytes()).unwrap();
+        let names: Vec<&str> = workload.tasks.iter().map(|task| task.name.as_str()).collect();
+
+        // Trace names are kept, unnamed pods are named after their id
+        assert_eq!(names, ["openb-pod-0017", "pod-1"]);
+
+        // Interned: every copy of a name shares one string
+        let name = Name::new("openb-pod-0017");
+        assert!(std::ptr::eq(name.as_str(), workload.tasks[0].name.as_str()));
+
+        // Keys share the one empty name
+        assert!(std::ptr::eq(workload.tasks[0].key().name.as_str(), workload.tasks[1].key().name.as_str()));
+    }
+
     #[apply(test_workload)]
     fn test_task_life_cycle(#[case] file_name: &str, prefix: &str) {
         let file_path = prefix.to_owned() + file_name;
         let file = File::open(&file_path)
-            .expect( format!("{} file not found", file_path ).as_str() );
+            .unwrap_or_else(|_| panic!("{} file not found", file_path));
 
-        let workload = WorkloadStruct::new(file_path, file);
+        let workload = WorkloadStruct::new(file_path, WorkloadMode::Sample, 0, file).unwrap();
 
         // Fetch 3 tasks
         let (a, b, c) =
@@ -257,5 +457,111 @@ mod tests {
         assert_eq!(workload.next_task(), a);
         assert_eq!(workload.next_task(), b);
         assert_eq!(workload.next_task(), c);
+
+        // Evicted tasks are retried in the same batch, before new ones arrive
+        workload.update_metrics(a.clone(), true);
+        workload.push_backlog(b.clone());
+        workload.evict(a.clone(), 0);
+        assert_eq!(workload.next_task(), a);
+        assert_eq!(workload.drain_backlog(), 0);
+        assert_eq!(workload.backlog_size(), 1);
+
+        // Bound again, the task still counts as scheduled once, and its first run as wasted
+        workload.update_metrics(a.clone(), true);
+        let (metrics, qos_metrics) = workload.deploy();
+        for m in [&metrics, &qos_metrics[&a.qos]] {
+            assert_eq!((m.tasks_scheduled, m.tasks_preempted), (1, 1));
+            assert_eq!((m.total_cpu, m.total_gpu), (a.cpu_milli, a.gpu_milli));
+            assert_eq!((m.wasted_cpu, m.wasted_gpu, m.wasted_time), (a.cpu_milli, a.gpu_milli, 0));
+        }
+    }
+
+    #[apply(test_workload)]
+    fn test_replay(#[case] file_name: &str, prefix: &str) {
+        let file_path = prefix.to_owned() + file_name;
+        let file = File::open(&file_path)
+            .unwrap_or_else(|_| panic!("{} file not found", file_path));
+
+        let workload = WorkloadStruct::new(file_path, WorkloadMode::Replay, 0, file);
+
+        // Multi-GPU traces have no timeline to replay
+        if file_name.starts_with("multigpu") {
+            assert!(matches!(workload, Err(Error::NoTimeline { .. })));
+            return;
+        }
+        let workload = workload.unwrap();
+
+        // Tasks arrive in creation order, and each task arrives exactly once
+        let mut prev = 0;
+        for _ in 0..workload.num_tasks {
+            assert!(!workload.exhausted());
+
+            let task = workload.next_task();
+            assert!(prev <= task.creation_time);
+            assert_eq!(workload.now(), task.creation_time);
+
+            prev = task.creation_time;
+        }
+        assert!(workload.exhausted());
+
+        // Delayed tasks are retried before the trace is considered done
+        let task = workload.arrivals[0].clone();
+        workload.push_backlog(task.clone());
+        workload.retry_backlog();
+
+        assert!(!workload.exhausted());
+        assert_eq!(workload.next_task(), task);
+        assert!(workload.exhausted());
+
+        // Next round replays the trace from the start
+        workload.deploy();
+        assert!(!workload.exhausted());
+        assert_eq!(workload.next_task(), workload.arrivals[0]);
+    }
+
+    #[apply(test_workload)]
+    fn test_qos(#[case] file_name: &str, prefix: &str) {
+        let file_path = prefix.to_owned() + file_name;
+        let file = File::open(&file_path)
+            .unwrap_or_else(|_| panic!("{} file not found", file_path));
+
+        let workload = WorkloadStruct::new(file_path, WorkloadMode::Sample, 0, file).unwrap();
+
+        // Multi-GPU traces have no qos column, so everything is Burstable
+        let ls = workload.tasks.iter().filter(|task| task.qos == Qos::LS ).count();
+        assert_eq!(ls == 0, file_name.starts_with("multigpu"));
+
+        for i in 0..100 {
+            let task = workload.next_task();
+            workload.update_metrics(task, i % 3 != 0);
+        }
+
+        // Classes add up to the totals
+        let (metrics, qos_metrics) = workload.deploy();
+        let sum = |f: fn(&TaskMetrics) -> usize| qos_metrics.values().map(f).sum::<usize>();
+
+        assert_eq!(sum(|m| m.tasks_arrived), metrics.tasks_arrived);
+        assert_eq!(sum(|m| m.tasks_scheduled), metrics.tasks_scheduled);
+        assert_eq!(sum(|m| m.tasks_delayed), metrics.tasks_delayed);
+        assert_eq!(qos_metrics.values().map(|m| m.total_gpu).sum::<GPU>(), metrics.total_gpu);
+
+        // Next batch starts from scratch
+        assert!(workload.deploy().1.is_empty());
+    }
+
+    #[apply(test_workload)]
+    fn test_seed(#[case] file_name: &str, prefix: &str) {
+        let file_path = prefix.to_owned() + file_name;
+
+        let open = || File::open(&file_path)
+            .unwrap_or_else(|_| panic!("{} file not found", file_path));
+
+        let a = WorkloadStruct::new(file_path.clone(), WorkloadMode::Sample, 7, open()).unwrap();
+        let b = WorkloadStruct::new(file_path.clone(), WorkloadMode::Sample, 7, open()).unwrap();
+
+        // Same seed, same tasks
+        for _ in 0..100 {
+            assert_eq!(a.next_task().id, b.next_task().id);
+        }
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,208 @@
+use crate::evaluator::*;
+use crate::types::*;
+
+// Simple Deciders
+// Each can be built from a "name" or "name=threshold" spec, and combined with commas.
+
+pub const DECIDERS: &[&str] = &[
+    "max_tasks_arrived",
+    "max_delayed",
+    "target_alloc",
+    "consecutive_failures",
+    "backlog",
+    "frag",
+];
+
+// Threshold of the max_delayed safety stop, which max_tasks_arrived gets unless one is given
+const MAX_DELAYED: POD = 500;
+
+// Parse a decider spec such as "max_tasks_arrived=10000,max_delayed=500".
+// Several deciders deploy as soon as any of them would.
+pub fn parse_decider( spec: &str ) -> Result<BoxedDecider, String> {
+    let mut deciders: Vec<BoxedDecider> = spec.split(',')
+        .map(|part| parse_single( part.trim() ))
+        .collect::<Result<_, _>>()?;
+
+    let names: Vec<&str> = spec.split(',')
+        .map(|part| part.split('=').next().unwrap_or_default().trim())
+        .collect();
+    if names.contains(&"max_tasks_arrived") && !names.contains(&"max_delayed") {
+        deciders.push(Box::new( MaxDelayed { max: MAX_DELAYED } ));
+    }
+
+    if deciders.len() == 1 {
+        return Ok(deciders.pop().unwrap());
+    }
+
+    Ok(Box::new( AnyDecider { deciders } ))
+}
+
+fn parse_single( spec: &str ) -> Result<BoxedDecider, String> {
+    let (name, value) = match spec.split_once('=') {
+        Some((name, value)) => (name, Some(value)),
+        None => (spec, None),
+    };
+
+    fn threshold<T: std::str::FromStr>( value: Option<&str>, default: T ) -> Result<T, String> {
+        value.map_or(Ok(default), |v| v.parse().map_err(|_| format!("invalid threshold: {}", v)))
+    }
+
+    let decider: BoxedDecider = match name {
+        "max_tasks_arrived" => Box::new( MaxTasksArrived { max: threshold(value, 10000)? } ),
+        "max_delayed" => Box::new( MaxDelayed { max: threshold(value, MAX_DELAYED)? } ),
+        "target_alloc" => Box::new( TargetAllocRate { rate: threshold(value, 0.9)? } ),
+        "consecutive_failures" => Box::new( ConsecutiveFailures::new( threshold(value, 100)? ) ),
+        "backlog" => Box::new( BacklogPercent { percent: threshold(value, 10.0)? } ),
+        "frag" => Box::new( FragThreshold { rate: threshold(value, 0.95)? } ),
+        _ => return Err(format!("unknown decider: {} (expected one of {})", name, DECIDERS.join(", "))),
+    };
+
+    Ok(decider)
+}
+
+
+// Deploy once enough tasks have arrived.
+// A spec naming it without max_delayed also gets max_delayed=500, so a full cluster still ends the batch
+#[derive(Debug, Clone)]
+#[public]
+struct MaxTasksArrived {
+    max: POD,
+}
+
+impl Decider for MaxTasksArrived {
+    fn name(&self) -> String { format!("max_tasks_arrived={}", self.max) }
+
+    fn deploy(&mut self, evaluator: &Evaluator ) -> bool {
+        evaluator.workload.metrics.borrow().tasks_arrived >= self.max
+    }
+}
+
+// Release valve: deploy once too many tasks failed to schedule
+#[derive(Debug, Clone)]
+#[public]
+struct MaxDelayed {
+    max: POD,
+}
+
+impl Decider for MaxDelayed {
+    fn name(&self) -> String { format!("max_delayed={}", self.max) }
+
+    fn deploy(&mut self, evaluator: &Evaluator ) -> bool {
+        evaluator.workload.metrics.borrow().tasks_delayed >= self.max
+    }
+}
+
+// Deploy once the cluster is allocated to the target rate (0 to 1)
+#[derive(Debug, Clone)]
+#[public]
+struct TargetAllocRate {
+    rate: f64,
+}
+
+impl Decider for TargetAllocRate {
+    fn name(&self) -> String { format!("target_alloc={}", self.rate) }
+
+    fn deploy(&mut self, evaluator: &Evaluator ) -> bool {
+        evaluator.cluster.metrics.borrow().alloc_rate >= self.rate
+    }
+}
+
+// Deploy once the fragmentation rate (0 to 1) crosses the threshold
+#[derive(Debug, Clone)]
+#[public]
+struct FragThreshold {
+    rate: f64,
+}
+
+impl Decider for FragThreshold {
+    fn name(&self) -> String { format!("frag={}", self.rate) }
+
+    fn deploy(&mut self, evaluator: &Evaluator ) -> bool {
+        evaluator.cluster.metrics.borrow().frag_rate >= self.rate
+    }
+}
+
+// The first tasks of a batch say little about the backlog, so wait for this many arrivals
+const BACKLOG_MIN_ARRIVED: POD = 100;
+
+// Deploy once the backlog exceeds a percentage of the tasks that arrived
+#[derive(Debug, Clone)]
+#[public]
+struct BacklogPercent {
+    percent: f64,
+}
+
+impl Decider for BacklogPercent {
+    fn name(&self) -> String { format!("backlog={}", self.percent) }
+
+    fn deploy(&mut self, evaluator: &Evaluator ) -> bool {
+        let arrived = evaluator.workload.metrics.borrow().tasks_arrived;
+        if arrived < BACKLOG_MIN_ARRIVED { return false; }
+
+        let backlog = evaluator.workload.backlog_size();
+        backlog as f64 * 100.0 > self.percent * arrived as f64
+    }
+}
+
+// Deploy once this many tasks in a row failed to schedule
+#[derive(Debug, Clone)]
+#[public]
+struct ConsecutiveFailures {
+    max: usize,
+
+    streak: usize,
+    last_scheduled: POD,
+}
+
+impl ConsecutiveFailures {
+    pub fn new( max: usize ) -> Self {
+        Self { max, streak: 0, last_scheduled: 0 }
+    }
+}
+
+impl Decider for ConsecutiveFailures {
+    fn name(&self) -> String { format!("consecutive_failures={}", self.max) }
+
+    // Called once per task, so the task just handled succeeded iff the scheduled count moved
+    fn deploy(&mut self, evaluator: &Evaluator ) -> bool {
+        let scheduled = evaluator.workload.metrics.borrow().tasks_scheduled;
+
+        if scheduled == self.last_scheduled {
+            self.streak += 1;
+        } else {
+            self.streak = 0;
+            self.last_scheduled = scheduled;
+        }
+
+        self.streak >= self.max
+    }
+
+    fn on_deploy(&mut self, _evaluator: &Evaluator ) {
+        self.streak = 0;
+        self.last_scheduled = 0;
+    }
+}
+
+// Deploy as soon as any of the deciders would. All of them are asked, so stateful ones stay in sync
+#[public]
+struct AnyDecider {
+    deciders: Vec<BoxedDecider>,
+}
+
+impl Decider for AnyDecider {
+    fn name(&self) -> String {
+        self.deciders.iter().map(|d| d.name()).collect::<Vec<_>>().join(",")
+    }
+
+    fn deploy(&mut self, evaluator: &Evaluator ) -> bool {
+        let mut deploy = false;
+        for decider in self.deciders.iter_mut() {
+            deploy |= decider.deploy(evaluator);
+        }
+        deploy
+    }
+
+    fn on_deploy(&mut self, evaluator: &Evaluator ) {
+        self.deciders.iter_mut().for_each(|d| d.on_deploy(evaluator));
+    }
+}
@@ -0,0 +1,299 @@
+use crate::evaluator::*;
+use crate::heuristics::plugins::*;
+use crate::heuristics::score_by::*;
+use crate::types::*;
+
+// Scheduling framework, in the style of kube-scheduler:
+// filter plugins drop nodes, weighted score plugins rank the rest, and a GPU plugin places the task on the winner.
+// By default scores are normalized across the candidate nodes before they are weighted, so weights compare plugins fairly
+
+// Drops nodes the task must not run on
+pub trait FilterPlugin {
+    fn name(&self) -> &str;
+
+    fn filter(&self, evaluator: &Evaluator, task: &PodSpecStruct, node: &NodeInfoStruct ) -> bool;
+
+    // Filters that only check for room, which preemption makes by evicting, so it skips them
+    fn capacity(&self) -> bool { false }
+}
+
+// Ranks the nodes that passed every filter
+pub trait ScorePlugin {
+    fn name(&self) -> &str;
+
+    fn score(&self, evaluator: &Evaluator, task: &PodSpecStruct, node: &NodeInfoStruct ) -> SCORE;
+
+    // Lower scores are better, e.g. leftover resources for best fit
+    fn minimize(&self) -> bool { false }
+
+    // Nodes the plugin has no score for are dropped, as if a filter had
+    fn feasible(&self, _evaluator: &Evaluator, _task: &PodSpecStruct, _node: &NodeInfoStruct ) -> bool { true }
+}
+
+// Picks the GPUs to bind on the selected node
+pub trait GpuPlugin {
+    fn name(&self) -> &str;
+
+    fn pick(&self, evaluator: &Evaluator, task: &PodSpec, node: &NodeInfoStruct ) -> Option<Vec<GpuInfo>>;
+}
+
+pub type BoxedFilter = Box<dyn FilterPlugin>;
+pub type BoxedScore = Box<dyn ScorePlugin>;
+pub type BoxedGpuPlugin = Box<dyn GpuPlugin>;
+
+
+// How a pipeline picks between nodes with the same score
+#[derive(Debug, Clone, Copy, Default)]
+#[derive(PartialEq, Eq)]
+pub enum Ties {
+    // First in node list order, which favors low node ids
+    #[default]
+    First,
+    // At random, from the cluster's seeded RNG
+    Random,
+    // Lowest node id, regardless of list order
+    NodeId,
+    // Node that was bound to longest ago this batch
+    LeastRecentlyUsed,
+}
+
+pub const TIES: &[&str] = &["first", "random", "node_id", "lru"];
+
+impl std::str::FromStr for Ties {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        match name {
+            "first" => Ok(Ties::First),
+            "random" => Ok(Ties::Random),
+            "node_id" => Ok(Ties::NodeId),
+            "lru" => Ok(Ties::LeastRecentlyUsed),
+            _ => Err(format!("unknown tie-break: {} (expected one of {})", name, TIES.join(", "))),
+        }
+    }
+}
+
+// How a pipeline combines the scores of its plugins
+#[derive(Debug, Clone, Copy, Default)]
+#[derive(PartialEq, Eq)]
+pub enum Combine {
+    // Each score min-max normalized across the nodes, then weighted
+    #[default]
+    Normalized,
+    // Raw scores weighted as they are, so plugins on large scales such as MiB dominate
+    Weighted,
+    // The first plugin decides, and later ones only settle its ties. Weights only give the direction
+    Lexicographic,
+    // Nodes no other node beats on every plugin, then the normalized score among them
+    Pareto,
+}
+
+pub const COMBINES: &[&str] = &["normalized", "weighted", "lexicographic", "pareto"];
+
+impl std::str::FromStr for Combine {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        match name {
+            "normalized" => Ok(Combine::Normalized),
+            "weighted" => Ok(Combine::Weighted),
+            "lexicographic" => Ok(Combine::Lexicographic),
+            "pareto" => Ok(Combine::Pareto),
+            _ => Err(format!("unknown score combination: {} (expected one of {})", name, COMBINES.join(", "))),
+        }
+    }
+}
+
+// Scheduler assembled from plugins. Nodes the task does not fit on are always filtered out first
+#[public]
+struct Pipeline {
+    name: String,
+
+    filters: Vec<BoxedFilter>,
+    scores: Vec<(WEIGHT, BoxedScore)>,
+    gpus: BoxedGpuPlugin,
+    ties: Ties,
+    combine: Combine,
+
+    // Nodes the first score plugin keeps before the rest score them, to spare expensive plugins. All of them if None
+    shortlist: Option<usize>,
+}
+
+impl Pipeline {
+    pub fn new( name: &str ) -> Self {
+        Self {
+            name: name.to_string(),
+            filters: Vec::new(),
+            scores: Vec::new(),
+            gpus: Box::new( MostAllocatedGpu ),
+            ties: Ties::default(),
+            combine: Combine::default(),
+            shortlist: None,
+        }
+    }
+
+    pub fn filter( mut self, plugin: impl FilterPlugin + 'static ) -> Self {
+        self.filters.push( Box::new(plugin) );
+        self
+    }
+
+    pub fn score( mut self, weight: WEIGHT, plugin: impl ScorePlugin + 'static ) -> Self {
+        self.scores.push( (weight, Box::new(plugin)) );
+        self
+    }
+
+    pub fn gpus( mut self, plugin: impl GpuPlugin + 'static ) -> Self {
+        self.gpus = Box::new(plugin);
+        self
+    }
+
+    pub fn ties( mut self, ties: Ties ) -> Self {
+        self.ties = ties;
+        self
+    }
+
+    pub fn combine( mut self, combine: Combine ) -> Self {
+        self.combine = combine;
+        self
+    }
+
+    pub fn shortlist( mut self, k: usize ) -> Self {
+        self.shortlist = Some(k);
+        self
+    }
+}
+
+impl Scheduler for Pipeline {
+    fn name(&self) -> &str { &self.name }
+
+    fn admits(&self, evaluator: &Evaluator, task: &PodSpecStruct, node: &NodeInfoStruct ) -> bool {
+        self.filters.iter()
+            .filter(|plugin| !plugin.capacity() )
+            .all(|plugin| plugin.filter( evaluator, task, node ))
+    }
+
+    fn schedule(&mut self, evaluator: &Evaluator, task: PodSpec ) -> Option<SchedulingPick> {
+        let cluster = &evaluator.cluster;
+
+        // Filter nodes
+        let nodes = cluster.filter_nodes( task.clone() )
+            .filter(|node_ref| {
+                let node = node_ref.borrow();
+                self.filters.iter().all(|plugin| plugin.filter( evaluator, &task, &node )) &&
+                    self.scores.iter().all(|(_, plugin)| plugin.feasible( evaluator, &task, &node ))
+            });
+
+        // Score nodes. Minimizing plugins count against the node
+        let score_funcs: Vec<_> = self.scores.iter()
+            .map(|(weight, plugin)| {
+                let weight = if plugin.minimize() { -weight } else { *weight };
+                let task = &task;
+                let score_func = move |node_ref: &NodeInfo| plugin.score( evaluator, task, &node_ref.borrow() );
+                (weight, score_func)
+            })
+            .collect();
+
+        // Shortlist by the first plugin alone
+        let nodes: Vec<NodeInfo> = match (self.shortlist, score_funcs.first()) {
+            (Some(k), Some((weight, score_func))) if *weight < 0.0 => nodes.top_k_by_min( k, |node_ref| {
+                let score = score_func( &node_ref );
+                (node_ref, score)
+            }),
+            (Some(k), Some((_, score_func))) => nodes.top_k_by_max( k, |node_ref| {
+                let score = score_func( &node_ref );
+                (node_ref, score)
+            }),
+            _ => nodes.collect(),
+        };
+        let nodes = nodes.into_iter();
+
+        // Higher is better for every plugin, for combinations that compare scores rather than add them up
+        let signed_funcs: Vec<_> = score_funcs.iter()
+            .map(|(weight, score_func)| {
+                let sign: SCORE = if *weight < 0.0 { -1 } else { 1 };
+                move |node_ref: &NodeInfo| sign * score_func( node_ref )
+            })
+            .collect();
+
+        let selected_node = {
+            // Only the random tie-break draws from the cluster's RNG, and GPU plugins may need it afterwards
+            let mut rng = (self.ties == Ties::Random).then(|| cluster.rng.borrow_mut());
+
+            let tie_break: &mut dyn TieBreak<NodeInfo> = match (self.ties, rng.as_mut()) {
+                (Ties::Random, Some(rng)) => &mut RandomTie(&mut **rng),
+                (Ties::NodeId, _) => &mut MinKeyTie(|node_ref: &NodeInfo| node_ref.borrow().spec.id ),
+                (Ties::LeastRecentlyUsed, _) => &mut MinKeyTie(|node_ref: &NodeInfo| cluster.last_bound( &node_ref.borrow() )),
+                _ => &mut FirstTie,
+            };
+
+            match self.combine {
+                Combine::Normalized => nodes.score_by_normalized_with( &score_funcs, tie_break ),
+                Combine::Weighted => nodes.score_by_weighted_with( &score_funcs, tie_break ),
+                Combine::Lexicographic => nodes.score_by_lexicographic_with( &signed_funcs, tie_break ),
+                Combine::Pareto => nodes.pareto_front( &signed_funcs ).into_iter()
+                    .score_by_normalized_with( &score_funcs, tie_break ),
+            }
+        };
+
+        // We have no nodes left to pick from!
+        let selected_node = selected_node?;
+        let node = selected_node.borrow().clone();
+
+        let gpus = self.gpus.pick( evaluator, &task, &node )?;
+
+        Some((selected_node, gpus))
+    }
+}
+
+
+// Parse a pipeline spec such as "dot_product*0.7+best_fit*0.3+model_penalty".
+// Terms are score plugins with an optional weight (1 by default), filter plugins,
+// a "gpus=<plugin>" term choosing how GPUs are picked, a "ties=<strategy>" term, a "combine=<mode>" term,
+// and a "shortlist=<k>" term keeping the k best nodes by the first score plugin.
+pub fn parse_pipeline( spec: &str ) -> Result<Pipeline, String> {
+    let mut pipeline = Pipeline::new( spec );
+
+    for term in spec.split('+').map(str::trim) {
+        if let Some(name) = term.strip_prefix("gpus=") {
+            pipeline.gpus = gpu_plugin_by_name( name )
+                .ok_or_else(|| format!("unknown GPU plugin: {} (expected one of {})", name, GPU_PLUGINS.join(", ")))?;
+            continue;
+        }
+
+        if let Some(name) = term.strip_prefix("ties=") {
+            pipeline.ties = name.parse()?;
+            continue;
+        }
+
+        if let Some(name) = term.strip_prefix("combine=") {
+            pipeline.combine = name.parse()?;
+            continue;
+        }
+
+        if let Some(k) = term.strip_prefix("shortlist=") {
+            pipeline.shortlist = Some(k.trim().parse().map_err(|_| format!("invalid shortlist: {}", k))?);
+            continue;
+        }
+
+        if let Some(filter) = filter_plugin_by_name( term ) {
+            pipeline.filters.push( filter );
+            continue;
+        }
+
+        let (name, weight) = match term.split_once('*') {
+            Some((name, weight)) => {
+                let weight = weight.trim().parse()
+                    .map_err(|_| format!("invalid weight: {}", weight))?;
+                (name.trim(), weight)
+            },
+            None => (term, 1.0),
+        };
+
+        let score = score_plugin_by_name( name )
+            .ok_or_else(|| format!("unknown plugin: {} (expected one of {}, {})",
+                                   name, FILTER_PLUGINS.join(", "), SCORE_PLUGINS.join(", ")))?;
+        pipeline.scores.push( (weight, score) );
+    }
+
+    Ok(pipeline)
+}
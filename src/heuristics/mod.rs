@@ -1,21 +1,38 @@
 #![allow(dead_code)]
 
-use crate::evaluator::*;
+use crate::types::*;
+use simple_schedulers::*;
 
 mod score_by;
 pub mod simple_schedulers;
+pub mod deciders;
+pub mod framework;
+pub mod plugins;
 
-// Simple Deciders
+pub use deciders::parse_decider;
+pub use framework::parse_pipeline;
 
-pub fn max_delayed( evaluator: &Evaluator ) -> bool {
-    let metrics = evaluator.workload.metrics.borrow();
+// Registries, so heuristics can be chosen by name at runtime
 
-    metrics.tasks_delayed >= 500
-}
+pub const SCHEDULERS: &[(&str, ScheduleFunc)] = &[
+    ("random", random_scheduler),
+    ("dot_product", dot_product_scheduler),
+    ("best_fit", best_fit_scheduler),
+    ("fgd", fgd_scheduler),
+    ("gpu_packing", gpu_packing_scheduler),
+    ("gpu_clustering", gpu_clustering_scheduler),
+];
 
-pub fn max_tasks_arrived( evaluator: &Evaluator ) -> bool {
-    let metrics = evaluator.workload.metrics.borrow();
+pub fn scheduler_by_name( name: &str ) -> Option<BoxedScheduler> {
+    SCHEDULERS.iter()
+        .find(|(n, _)| *n == name)
+        .map(|&(name, func)| Box::new(FnScheduler::new(name, func)) as BoxedScheduler )
+}
 
-    // Allow for release valve by checking that delayed tasks are not too high
-    metrics.tasks_arrived >= 10000 || max_delayed( evaluator)
-}
\ No newline at end of file
+// Registered scheduler by name, otherwise a plugin pipeline spec
+pub fn parse_scheduler( spec: &str ) -> Result<BoxedScheduler, String> {
+    match scheduler_by_name( spec ) {
+        Some(scheduler) => Ok(scheduler),
+        None => parse_pipeline( spec ).map(|pipeline| Box::new(pipeline) as BoxedScheduler ),
+    }
+}
@@ -0,0 +1,285 @@
+use rand::prelude::IteratorRandom;
+use crate::evaluator::*;
+use crate::heuristics::framework::*;
+use crate::heuristics::score_by::ScoreBy;
+use crate::types::*;
+
+// Built-in plugins for the scheduling framework, by name
+
+pub const FILTER_PLUGINS: &[&str] = &["exclusive_model", "frag_fit"];
+pub const SCORE_PLUGINS: &[&str] = &["dot_product", "best_fit", "model_penalty", "gpu_packing", "gpu_clustering", "least_allocated", "frag"];
+pub const GPU_PLUGINS: &[&str] = &["most_allocated", "random", "fgd", "gpu_clustering"];
+
+pub fn filter_plugin_by_name( name: &str ) -> Option<BoxedFilter> {
+    match name {
+        "exclusive_model" => Some(Box::new( ExclusiveModel )),
+        "frag_fit" => Some(Box::new( FragFit )),
+        _ => None,
+    }
+}
+
+pub fn score_plugin_by_name( name: &str ) -> Option<BoxedScore> {
+    match name {
+        "dot_product" => Some(Box::new( DotProduct )),
+        "best_fit" => Some(Box::new( BestFit )),
+        "model_penalty" => Some(Box::new( ModelPenalty )),
+        "gpu_packing" => Some(Box::new( GpuPacking )),
+        "gpu_clustering" => Some(Box::new( GpuClustering )),
+        "least_allocated" => Some(Box::new( LeastAllocated )),
+        "frag" => Some(Box::new( FragGrowth )),
+        _ => None,
+    }
+}
+
+pub fn gpu_plugin_by_name( name: &str ) -> Option<BoxedGpuPlugin> {
+    match name {
+        "most_allocated" => Some(Box::new( MostAllocatedGpu )),
+        "random" => Some(Box::new( RandomGpu )),
+        "fgd" => Some(Box::new( FgdGpu )),
+        "gpu_clustering" => Some(Box::new( ClusteredGpu )),
+        _ => None,
+    }
+}
+
+
+// Filter Plugins
+
+// Keep tasks without a model off model machines altogether
+pub struct ExclusiveModel;
+
+impl FilterPlugin for ExclusiveModel {
+    fn name(&self) -> &str { "exclusive_model" }
+
+    fn filter(&self, _evaluator: &Evaluator, task: &PodSpecStruct, node: &NodeInfoStruct ) -> bool {
+        !task.model.is_empty() || node.spec.model.is_empty()
+    }
+}
+
+// Nodes where the GPUs left cannot hold the task, by FGD's stricter per-GPU accounting
+pub struct FragFit;
+
+impl FilterPlugin for FragFit {
+    fn name(&self) -> &str { "frag_fit" }
+
+    fn filter(&self, evaluator: &Evaluator, task: &PodSpecStruct, node: &NodeInfoStruct ) -> bool {
+        evaluator.cluster.frag_delta( task, node ) != FRAG::MAX
+    }
+
+    fn capacity(&self) -> bool { true }
+}
+
+
+// Score Plugins
+
+// Resources left on the node, weighted by what the task asks for
+pub struct DotProduct;
+
+impl ScorePlugin for DotProduct {
+    fn name(&self) -> &str { "dot_product" }
+
+    fn score(&self, _evaluator: &Evaluator, task: &PodSpecStruct, node: &NodeInfoStruct ) -> SCORE {
+        node.cpu_rem as SCORE * task.cpu_milli as SCORE
+            + node.mem_rem as SCORE * task.memory_mib as SCORE
+            + node.gpu_unallocated as SCORE * task.gpu_milli as SCORE
+            + node.gpu_memory_mib() as SCORE * (task.gpu_memory_mib * task.num_gpu as MEM) as SCORE
+    }
+}
+
+// Resources left on the node once the task is placed
+pub struct BestFit;
+
+impl ScorePlugin for BestFit {
+    fn name(&self) -> &str { "best_fit" }
+
+    fn score(&self, _evaluator: &Evaluator, task: &PodSpecStruct, node: &NodeInfoStruct ) -> SCORE {
+        node.cpu_rem as SCORE - task.cpu_milli as SCORE
+            + node.mem_rem as SCORE - task.memory_mib as SCORE
+            + node.gpu_unallocated as SCORE - task.gpu_milli as SCORE
+    }
+
+    fn minimize(&self) -> bool { true }
+}
+
+// Resources left on the node, so load spreads out
+pub struct LeastAllocated;
+
+impl ScorePlugin for LeastAllocated {
+    fn name(&self) -> &str { "least_allocated" }
+
+    fn score(&self, _evaluator: &Evaluator, _task: &PodSpecStruct, node: &NodeInfoStruct ) -> SCORE {
+        node.cpu_rem as SCORE + node.mem_rem as SCORE + node.gpu_unallocated as SCORE
+    }
+}
+
+const MODEL_PENALTY: SCORE = 10000;
+
+// Prefer not to run tasks without a model on model machines
+pub struct ModelPenalty;
+
+impl ScorePlugin for ModelPenalty {
+    fn name(&self) -> &str { "model_penalty" }
+
+    fn score(&self, _evaluator: &Evaluator, task: &PodSpecStruct, node: &NodeInfoStruct ) -> SCORE {
+        if task.model.is_empty() && !node.spec.model.is_empty() { MODEL_PENALTY } else { 0 }
+    }
+
+    fn minimize(&self) -> bool { true }
+}
+
+// Score tiers are spaced far enough apart that the secondary score never crosses into the next tier.
+// Pipelines normalize scores as floats, so keep them well within f64 precision
+pub const TIER: SCORE = 1 << 32;
+
+fn tiered( tier: SCORE, score: impl Into<SCORE> ) -> SCORE {
+    tier * TIER + score.into()
+}
+
+// Partially used GPUs first, then nodes with some GPUs in use, then idle nodes
+pub struct GpuPacking;
+
+impl ScorePlugin for GpuPacking {
+    fn name(&self) -> &str { "gpu_packing" }
+
+    fn score(&self, _evaluator: &Evaluator, task: &PodSpecStruct, node: &NodeInfoStruct ) -> SCORE {
+        let shares_gpu = task.single_gpu() && node.gpu_rem.iter()
+            .map(|gpu| gpu.borrow() )
+            .any(|gpu| gpu.fits( task ) && gpu.gpu_milli < GPU_MILLI );
+
+        let tier: SCORE = if shares_gpu {
+            0
+        } else if node.gpu_unallocated < node.spec.gpu_milli {
+            1
+        } else {
+            2
+        };
+
+        tiered( tier, node.gpu_unallocated )
+    }
+
+    fn minimize(&self) -> bool { true }
+}
+
+// GPU Clustering tiers: GPUs holding tasks of the same share first, then idle GPUs, then GPUs holding other sizes
+fn share_tier( task: &PodSpecStruct, gpu: &GpuInfoStruct ) -> SCORE {
+    if gpu.share == task.gpu_milli {
+        0
+    } else if gpu.gpu_milli == GPU_MILLI {
+        1
+    } else {
+        2
+    }
+}
+
+// Nodes by the best tier of their GPUs, so tasks of the same share end up together.
+// CPU-only tasks go wherever GPUs are least wasted
+pub struct GpuClustering;
+
+impl ScorePlugin for GpuClustering {
+    fn name(&self) -> &str { "gpu_clustering" }
+
+    fn score(&self, _evaluator: &Evaluator, task: &PodSpecStruct, node: &NodeInfoStruct ) -> SCORE {
+        let tier: SCORE = if task.num_gpu == 0 { 0 } else {
+            node.gpu_rem.iter()
+                .map(|gpu| gpu.borrow() )
+                .filter(|gpu| gpu.fits( task ) )
+                .map(|gpu| share_tier( task, &gpu ) )
+                .min()
+                .unwrap_or(2)
+        };
+
+        tiered( tier, node.gpu_unallocated )
+    }
+
+    fn minimize(&self) -> bool { true }
+}
+
+
+// Fragmentation Gradient Descent (ATC'23): growth in fragmentation from placing the task here.
+// Nodes FGD can't fit on are dropped, as frag_fit would
+pub struct FragGrowth;
+
+impl ScorePlugin for FragGrowth {
+    fn name(&self) -> &str { "frag" }
+
+    fn score(&self, evaluator: &Evaluator, task: &PodSpecStruct, node: &NodeInfoStruct ) -> SCORE {
+        evaluator.cluster.frag_delta( task, node ) as SCORE
+    }
+
+    fn minimize(&self) -> bool { true }
+
+    fn feasible(&self, evaluator: &Evaluator, task: &PodSpecStruct, node: &NodeInfoStruct ) -> bool {
+        FragFit.filter( evaluator, task, node )
+    }
+}
+
+
+// GPU Plugins
+
+// Single-GPU tasks go to the fullest GPU that fits. Multi-GPU tasks take the first free GPUs
+pub struct MostAllocatedGpu;
+
+impl GpuPlugin for MostAllocatedGpu {
+    fn name(&self) -> &str { "most_allocated" }
+
+    fn pick(&self, _evaluator: &Evaluator, task: &PodSpec, node: &NodeInfoStruct ) -> Option<Vec<GpuInfo>> {
+        let gpus = node.filter_gpus( task.clone() );
+
+        if task.single_gpu() {
+            let gpu = gpus.score_by_min(|gpu| {
+                let score = gpu.borrow().gpu_milli as SCORE;
+                (gpu, score)
+            })?;
+            Some(vec![gpu])
+        } else {
+            Some(gpus.take( task.num_gpu ).collect())
+        }
+    }
+}
+
+pub struct RandomGpu;
+
+impl GpuPlugin for RandomGpu {
+    fn name(&self) -> &str { "random" }
+
+    fn pick(&self, evaluator: &Evaluator, task: &PodSpec, node: &NodeInfoStruct ) -> Option<Vec<GpuInfo>> {
+        Some(node
+            .filter_gpus( task.clone() )
+            .choose_multiple( &mut evaluator.cluster.rng.borrow_mut(), task.num_gpu ))
+    }
+}
+
+// Single-GPU tasks go to the fullest GPU of the best GPU Clustering tier. Multi-GPU tasks take the first free GPUs
+pub struct ClusteredGpu;
+
+impl GpuPlugin for ClusteredGpu {
+    fn name(&self) -> &str { "gpu_clustering" }
+
+    fn pick(&self, _evaluator: &Evaluator, task: &PodSpec, node: &NodeInfoStruct ) -> Option<Vec<GpuInfo>> {
+        let gpus = node.filter_gpus( task.clone() );
+
+        if task.single_gpu() {
+            let gpu = gpus.score_by_min(|gpu| {
+                let score = tiered( share_tier( task, &gpu.borrow() ), gpu.borrow().gpu_milli );
+                (gpu, score)
+            })?;
+            Some(vec![gpu])
+        } else {
+            Some(gpus.take( task.num_gpu ).collect())
+        }
+    }
+}
+
+// GPUs that grow fragmentation the least
+pub struct FgdGpu;
+
+impl GpuPlugin for FgdGpu {
+    fn name(&self) -> &str { "fgd" }
+
+    fn pick(&self, evaluator: &Evaluator, task: &PodSpec, node: &NodeInfoStruct ) -> Option<Vec<GpuInfo>> {
+        let (gpu_ids, _) = node.frag_state().best_gpus( task, &evaluator.cluster.workload )?;
+
+        Some(gpu_ids.into_iter()
+            .map(|id| node.gpu_rem[id].clone())
+            .collect())
+    }
+}
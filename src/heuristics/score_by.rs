@@ -1,40 +1,422 @@
 
+use std::cmp::Ordering;
+use rand::Rng;
+use rand::prelude::IteratorRandom;
 use crate::types::*;
 
 // Used AI to help generate the Iterator trait
 
-// Trait to extend Iterator with scoring methods
+// Trait to extend Iterator with scoring methods.
+// Scores can be any partially ordered type: SCORE, floats, or tuples for lexicographic order
 pub trait ScoreBy: Iterator {
-    fn score_by_max<S>(self, score_func: S) -> Option<Self::Item>
+    fn score_by_max<K, S>(self, score_func: S) -> Option<Self::Item>
     where
         Self: Sized,
-        S: Fn(Self::Item) -> (Self::Item, SCORE);
-        
-    fn score_by_min<S>(self, score_func: S) -> Option<Self::Item>
+        K: PartialOrd,
+        S: Fn(Self::Item) -> (Self::Item, K);
+
+    fn score_by_min<K, S>(self, score_func: S) -> Option<Self::Item>
+    where
+        Self: Sized,
+        K: PartialOrd,
+        S: Fn(Self::Item) -> (Self::Item, K);
+
+    // Like score_by_max and score_by_min, but ties are settled by the given strategy
+    fn score_by_max_with<K, S>(self, score_func: S, tie_break: &mut dyn TieBreak<Self::Item>) -> Option<Self::Item>
+    where
+        Self: Sized,
+        K: PartialOrd,
+        S: Fn(Self::Item) -> (Self::Item, K);
+
+    fn score_by_min_with<K, S>(self, score_func: S, tie_break: &mut dyn TieBreak<Self::Item>) -> Option<Self::Item>
+    where
+        Self: Sized,
+        K: PartialOrd,
+        S: Fn(Self::Item) -> (Self::Item, K);
+
+    // The k best items, best first. Equal scores keep iteration order, also where they straddle the k-th place.
+    // Lets a scheduler shortlist candidates before a more expensive second pass
+    fn top_k_by_max<K, S>(self, k: usize, score_func: S) -> Vec<Self::Item>
+    where
+        Self: Sized,
+        K: PartialOrd,
+        S: Fn(Self::Item) -> (Self::Item, K);
+
+    fn top_k_by_min<K, S>(self, k: usize, score_func: S) -> Vec<Self::Item>
+    where
+        Self: Sized,
+        K: PartialOrd,
+        S: Fn(Self::Item) -> (Self::Item, K);
+
+    // Highest weighted sum of scores wins. Ties go to the first item
+    fn score_by_weighted<F>(self, scorers: &[(WEIGHT, F)]) -> Option<Self::Item>
+    where
+        Self: Sized,
+        F: Fn(&Self::Item) -> SCORE;
+
+    fn score_by_weighted_with<F>(self, scorers: &[(WEIGHT, F)], tie_break: &mut dyn TieBreak<Self::Item>) -> Option<Self::Item>
     where
         Self: Sized,
-        S: Fn(Self::Item) -> (Self::Item, SCORE);
+        F: Fn(&Self::Item) -> SCORE;
+
+    // Like score_by_weighted, but each score is first min-max normalized to [0, 1] across the items,
+    // so scores on different scales (CPU milli, MiB, GPU milli) weigh in fairly
+    fn score_by_normalized<F>(self, scorers: &[(WEIGHT, F)]) -> Option<Self::Item>
+    where
+        Self: Sized,
+        F: Fn(&Self::Item) -> SCORE;
+
+    fn score_by_normalized_with<F>(self, scorers: &[(WEIGHT, F)], tie_break: &mut dyn TieBreak<Self::Item>) -> Option<Self::Item>
+    where
+        Self: Sized,
+        F: Fn(&Self::Item) -> SCORE;
+
+    // Highest first score wins, ties are broken by the next score, and so on
+    fn score_by_lexicographic<F>(self, scorers: &[F]) -> Option<Self::Item>
+    where
+        Self: Sized,
+        F: Fn(&Self::Item) -> SCORE;
+
+    fn score_by_lexicographic_with<F>(self, scorers: &[F], tie_break: &mut dyn TieBreak<Self::Item>) -> Option<Self::Item>
+    where
+        Self: Sized,
+        F: Fn(&Self::Item) -> SCORE;
+
+    // Items no other item beats on every score at once (higher is better), in iteration order
+    fn pareto_front<F>(self, scorers: &[F]) -> Vec<Self::Item>
+    where
+        Self: Sized,
+        F: Fn(&Self::Item) -> SCORE;
+}
+
+// Picks one of the items that tie for the best score
+pub trait TieBreak<T> {
+    fn pick(&mut self, tied: Vec<T>) -> Option<T>;
+}
+
+// First in iteration order. For nodes, that favors low node ids
+pub struct FirstTie;
+
+impl<T> TieBreak<T> for FirstTie {
+    fn pick(&mut self, tied: Vec<T>) -> Option<T> { tied.into_iter().next() }
+}
+
+// Uniformly at random. Pass a seeded RNG to keep runs reproducible
+pub struct RandomTie<'a, R: Rng>(pub &'a mut R);
+
+impl<T, R: Rng> TieBreak<T> for RandomTie<'_, R> {
+    fn pick(&mut self, tied: Vec<T>) -> Option<T> { tied.into_iter().choose(self.0) }
+}
+
+// Lowest key, such as node id, or last use for least recently used. Equal keys keep iteration order
+pub struct MinKeyTie<F>(pub F);
+
+impl<T, K: Ord, F: Fn(&T) -> K> TieBreak<T> for MinKeyTie<F> {
+    fn pick(&mut self, tied: Vec<T>) -> Option<T> { tied.into_iter().min_by_key(&self.0) }
+}
+
+// Every item whose score ties for the best. `better` is Greater to maximize, Less to minimize
+fn best_tied<T, K: PartialOrd>( scored: impl Iterator<Item=(T, K)>, better: Ordering ) -> Vec<T> {
+    let mut best: Option<K> = None;
+    let mut tied = Vec::new();
+
+    for (item, score) in scored {
+        let ordering = best.as_ref().map_or(better, |best| compare(&score, best));
+
+        if ordering == better {
+            best = Some(score);
+            tied.clear();
+            tied.push(item);
+        } else if ordering == Ordering::Equal {
+            tied.push(item);
+        }
+    }
+
+    tied
+}
+
+// Incomparable scores (NaN) count as equal
+fn compare<K: PartialOrd>( a: &K, b: &K ) -> Ordering {
+    a.partial_cmp(b).unwrap_or(Ordering::Equal)
 }
 
 // Implement ScoreBy for all types that implement Iterator
 impl<T: Iterator> ScoreBy for T {
-    fn score_by_max<S>(self, score_func: S) -> Option<Self::Item>
+    fn score_by_max<K, S>(self, score_func: S) -> Option<Self::Item>
     where
         Self: Sized,
-        S: Fn(Self::Item) -> (Self::Item, SCORE),
+        K: PartialOrd,
+        S: Fn(Self::Item) -> (Self::Item, K),
     {
         self.map(score_func)
-            .max_by_key(|(_, score)| *score)
+            .max_by(|(_, a), (_, b)| compare(a, b))
             .map(|(item, _)| item)
     }
-    
-    fn score_by_min<S>(self, score_func: S) -> Option<Self::Item>
+
+    fn score_by_min<K, S>(self, score_func: S) -> Option<Self::Item>
     where
         Self: Sized,
-        S: Fn(Self::Item) -> (Self::Item, SCORE),
+        K: PartialOrd,
+        S: Fn(Self::Item) -> (Self::Item, K),
     {
         self.map(score_func)
-            .min_by_key(|(_, score)| *score)
+            .min_by(|(_, a), (_, b)| compare(a, b))
             .map(|(item, _)| item)
     }
-}
\ No newline at end of file
+
+    fn score_by_max_with<K, S>(self, score_func: S, tie_break: &mut dyn TieBreak<Self::Item>) -> Option<Self::Item>
+    where
+        Self: Sized,
+        K: PartialOrd,
+        S: Fn(Self::Item) -> (Self::Item, K),
+    {
+        tie_break.pick(best_tied(self.map(score_func), Ordering::Greater))
+    }
+
+    fn score_by_min_with<K, S>(self, score_func: S, tie_break: &mut dyn TieBreak<Self::Item>) -> Option<Self::Item>
+    where
+        Self: Sized,
+        K: PartialOrd,
+        S: Fn(Self::Item) -> (Self::Item, K),
+    {
+        tie_break.pick(best_tied(self.map(score_func), Ordering::Less))
+    }
+
+    fn top_k_by_max<K, S>(self, k: usize, score_func: S) -> Vec<Self::Item>
+    where
+        Self: Sized,
+        K: PartialOrd,
+        S: Fn(Self::Item) -> (Self::Item, K),
+    {
+        // Stable sort, so equal scores stay in iteration order
+        let mut scored: Vec<_> = self.map(score_func).collect();
+        scored.sort_by(|(_, a), (_, b)| compare(b, a));
+
+        scored.into_iter().take(k).map(|(item, _)| item).collect()
+    }
+
+    fn top_k_by_min<K, S>(self, k: usize, score_func: S) -> Vec<Self::Item>
+    where
+        Self: Sized,
+        K: PartialOrd,
+        S: Fn(Self::Item) -> (Self::Item, K),
+    {
+        let mut scored: Vec<_> = self.map(score_func).collect();
+        scored.sort_by(|(_, a), (_, b)| compare(a, b));
+
+        scored.into_iter().take(k).map(|(item, _)| item).collect()
+    }
+
+    fn score_by_weighted<F>(self, scorers: &[(WEIGHT, F)]) -> Option<Self::Item>
+    where
+        Self: Sized,
+        F: Fn(&Self::Item) -> SCORE,
+    {
+        self.score_by_weighted_with(scorers, &mut FirstTie)
+    }
+
+    fn score_by_weighted_with<F>(self, scorers: &[(WEIGHT, F)], tie_break: &mut dyn TieBreak<Self::Item>) -> Option<Self::Item>
+    where
+        Self: Sized,
+        F: Fn(&Self::Item) -> SCORE,
+    {
+        let weighted = self.map(|item| {
+            let score: f64 = scorers.iter()
+                .map(|(weight, score_func)| weight * score_func(&item) as f64 )
+                .sum();
+            (item, score)
+        });
+
+        tie_break.pick(best_tied(weighted, Ordering::Greater))
+    }
+
+    fn score_by_normalized<F>(self, scorers: &[(WEIGHT, F)]) -> Option<Self::Item>
+    where
+        Self: Sized,
+        F: Fn(&Self::Item) -> SCORE,
+    {
+        self.score_by_normalized_with(scorers, &mut FirstTie)
+    }
+
+    fn score_by_normalized_with<F>(self, scorers: &[(WEIGHT, F)], tie_break: &mut dyn TieBreak<Self::Item>) -> Option<Self::Item>
+    where
+        Self: Sized,
+        F: Fn(&Self::Item) -> SCORE,
+    {
+        // Every item has to be scored before any can be normalized
+        let scored: Vec<(Self::Item, Vec<SCORE>)> = self
+            .map(|item| {
+                let scores = scorers.iter().map(|(_, score_func)| score_func(&item) ).collect();
+                (item, scores)
+            })
+            .collect();
+
+        let ranges: Vec<(SCORE, SCORE)> = (0..scorers.len())
+            .map(|i| {
+                let column = scored.iter().map(|(_, scores)| scores[i] );
+                (column.clone().min().unwrap_or_default(), column.max().unwrap_or_default())
+            })
+            .collect();
+
+        // Scores that are the same on every item can't tell them apart
+        let normalized = scored.into_iter().map(|(item, scores)| {
+            let score: f64 = scores.iter().zip(&ranges).zip(scorers)
+                .filter(|((_, (min, max)), _)| max > min )
+                .map(|((score, (min, max)), (weight, _))| weight * (score - min) as f64 / (max - min) as f64 )
+                .sum();
+            (item, score)
+        });
+
+        tie_break.pick(best_tied(normalized, Ordering::Greater))
+    }
+
+    fn score_by_lexicographic<F>(self, scorers: &[F]) -> Option<Self::Item>
+    where
+        Self: Sized,
+        F: Fn(&Self::Item) -> SCORE,
+    {
+        self.score_by_lexicographic_with(scorers, &mut FirstTie)
+    }
+
+    fn score_by_lexicographic_with<F>(self, scorers: &[F], tie_break: &mut dyn TieBreak<Self::Item>) -> Option<Self::Item>
+    where
+        Self: Sized,
+        F: Fn(&Self::Item) -> SCORE,
+    {
+        let scored = self.map(|item| {
+            let scores: Vec<SCORE> = scorers.iter().map(|score_func| score_func(&item) ).collect();
+            (item, scores)
+        });
+
+        tie_break.pick(best_tied(scored, Ordering::Greater))
+    }
+
+    fn pareto_front<F>(self, scorers: &[F]) -> Vec<Self::Item>
+    where
+        Self: Sized,
+        F: Fn(&Self::Item) -> SCORE,
+    {
+        let scored: Vec<(Self::Item, Vec<SCORE>)> = self
+            .map(|item| {
+                let scores = scorers.iter().map(|score_func| score_func(&item) ).collect();
+                (item, scores)
+            })
+            .collect();
+
+        // At least as good on every score and better on one
+        let dominates = |a: &[SCORE], b: &[SCORE]| {
+            a.iter().zip(b).all(|(a, b)| a >= b) && a.iter().zip(b).any(|(a, b)| a > b)
+        };
+
+        let dominated: Vec<bool> = scored.iter()
+            .map(|(_, scores)| scored.iter().any(|(_, other)| dominates(other, scores)) )
+            .collect();
+
+        scored.into_iter().zip(dominated)
+            .filter(|(_, dominated)| !dominated )
+            .map(|((item, _), _)| item)
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+    use rstest::rstest;
+
+    // (CPU milli left, GPU milli left) of some candidate nodes
+    type Node = (SCORE, SCORE);
+    const NODES: [Node; 4] = [(4000, 500), (96000, 0), (8000, 1000), (8000, 1000)];
+
+    // Nodes 2 and 3 tie on GPU but not on CPU, so tie-breaks can be told apart
+    static TIED: [Node; 4] = [(4000, 500), (96000, 0), (8000, 1000), (6000, 1000)];
+
+    fn cpu( node: &&Node ) -> SCORE { node.0 }
+    fn gpu( node: &&Node ) -> SCORE { node.1 }
+
+    type Scorer = fn(&&Node) -> SCORE;
+
+    #[rstest]
+    fn test_extremum() {
+        assert_eq!(NODES.iter().score_by_max(|n| (n, n.1 as f64 / 3.0)), Some(&NODES[3]));
+        assert_eq!(NODES.iter().score_by_min(|n| (n, -n.0)), Some(&NODES[1]));
+
+        // Tuples break ties lexicographically
+        assert_eq!(NODES.iter().score_by_min(|n| (n, (n.1, n.0))), Some(&NODES[1]));
+    }
+
+    #[rstest]
+    fn test_weighted() {
+        let scorers: [(WEIGHT, Scorer); 2] = [(1.0, cpu), (1.0, gpu)];
+
+        // CPU milli dwarfs GPU milli unless normalized
+        assert_eq!(NODES.iter().score_by_weighted(&scorers), Some(&NODES[1]));
+        assert_eq!(NODES.iter().score_by_normalized(&scorers), Some(&NODES[2]));
+
+        // A negative weight prefers low scores. Constant scores are ignored
+        let scorers: [(WEIGHT, Scorer); 2] = [(-1.0, cpu), (5.0, |_| 7)];
+        assert_eq!(NODES.iter().score_by_normalized(&scorers), Some(&NODES[0]));
+        assert_eq!(NODES[1..2].iter().score_by_normalized(&scorers), Some(&NODES[1]));
+        assert_eq!(NODES[..0].iter().score_by_normalized(&scorers), None);
+    }
+
+    #[rstest]
+    fn test_lexicographic() {
+        let gpu_then_cpu: [Scorer; 2] = [gpu, cpu];
+        assert_eq!(NODES.iter().score_by_lexicographic(&gpu_then_cpu), Some(&NODES[2]));
+
+        let cpu_then_gpu: [Scorer; 2] = [cpu, gpu];
+        assert_eq!(NODES.iter().score_by_lexicographic(&cpu_then_gpu), Some(&NODES[1]));
+    }
+
+    #[rstest]
+    fn test_ties() {
+        let gpu_left = |n: &'static Node| (n, n.1);
+
+        assert_eq!(TIED.iter().score_by_max_with(gpu_left, &mut FirstTie), Some(&TIED[2]));
+        assert_eq!(TIED.iter().score_by_min_with(gpu_left, &mut FirstTie), Some(&TIED[1]));
+
+        // Prefer the node used longest ago
+        let last_used = [3, 0, 2, 1];
+        let lru = |n: &&Node| last_used[TIED.iter().position(|m| std::ptr::eq(m, *n)).unwrap()];
+        assert_eq!(TIED.iter().score_by_max_with(gpu_left, &mut MinKeyTie(lru)), Some(&TIED[3]));
+
+        // Random ties are reproducible and never leave the tied set
+        let pick = |seed| {
+            let mut rng = StdRng::seed_from_u64(seed);
+            TIED.iter().score_by_max_with(gpu_left, &mut RandomTie(&mut rng))
+        };
+        assert!((0..20).all(|seed| pick(seed) == pick(seed)));
+        assert!((0..20).all(|seed| matches!(pick(seed), Some(n) if n.1 == 1000)));
+        assert!((0..20).any(|seed| pick(seed) != pick(0)));
+
+        let scorers: [(WEIGHT, Scorer); 1] = [(1.0, gpu)];
+        assert_eq!(TIED.iter().score_by_normalized_with(&scorers, &mut MinKeyTie(|n: &&Node| n.0)), Some(&TIED[3]));
+    }
+
+    #[rstest]
+    fn test_top_k() {
+        assert_eq!(TIED.iter().top_k_by_max(3, |n| (n, n.1)), vec![&TIED[2], &TIED[3], &TIED[0]]);
+        assert_eq!(TIED.iter().top_k_by_min(2, |n| (n, n.1)), vec![&TIED[1], &TIED[0]]);
+
+        // Nodes 2 and 3 tie for the k-th place, and the first in iteration order gets it
+        assert_eq!(TIED.iter().top_k_by_max(1, |n| (n, n.1)), vec![&TIED[2]]);
+        assert_eq!(NODES.iter().top_k_by_min(2, |n| (n, n.0)), vec![&NODES[0], &NODES[2]]);
+        assert_eq!(NODES.iter().top_k_by_max(2, |n| (n, n.0)), vec![&NODES[1], &NODES[2]]);
+
+        // Ties past the k-th place do not change what is kept
+        assert_eq!(NODES.iter().top_k_by_max(1, |n| (n, n.1)), vec![&NODES[2]]);
+        assert_eq!(TIED.iter().top_k_by_max(2, |n| (n, n.1)), vec![&TIED[2], &TIED[3]]);
+
+        assert_eq!(NODES.iter().top_k_by_min(9, |n| (n, n.0)).len(), NODES.len());
+        assert!(NODES.iter().top_k_by_max(0, |n| (n, n.0)).is_empty());
+    }
+
+    #[rstest]
+    fn test_pareto() {
+        let scorers: [Scorer; 2] = [cpu, gpu];
+        assert_eq!(NODES.iter().pareto_front(&scorers), vec![&NODES[1], &NODES[2], &NODES[3]]);
+    }
+}
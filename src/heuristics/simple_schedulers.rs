@@ -1,23 +1,19 @@
 use rand::prelude::IteratorRandom;
 use crate::evaluator::*;
-use crate::heuristics::score_by::ScoreBy;
+use crate::heuristics::framework::*;
+use crate::heuristics::plugins::*;
+use crate::heuristics::score_by::{RandomTie, ScoreBy};
 use crate::types::*;
 
 
 // Simple Schedulers
 
 pub fn random_scheduler( evaluator: &Evaluator, task: PodSpec ) -> Option<SchedulingPick> {
-    let cluster = evaluator.cluster.clone();
+    let cluster = &evaluator.cluster;
 
     let nodes = cluster.filter_nodes( task.clone() );
-    let node_opt = nodes.choose( &mut cluster.rng.borrow_mut() );
-
     // We have no nodes left to pick from!
-    if node_opt.is_none() {
-        return None;
-    }
-
-    let selected_node  = node_opt.unwrap();
+    let selected_node = nodes.choose( &mut cluster.rng.borrow_mut() )?;
     let node = selected_node.borrow().clone();
 
     let gpus = node
@@ -28,93 +24,287 @@ pub fn random_scheduler( evaluator: &Evaluator, task: PodSpec ) -> Option<Schedu
 }
 
 
-const MODEL_PENALTY: SCORE = 10000;
-pub fn dot_product_scheduler( evaluator: &Evaluator, task: PodSpec ) -> Option<SchedulingPick> {
-    let task = task.to_owned();
+// Simple schedulers score nodes with the same plugins as pipelines, but add up the raw scores
+// instead of normalizing them, and place GPUs like the most_allocated plugin.
+// Nodes with the same score are picked between at random with the cluster's seeded RNG, so low node ids are not favored
 
-    let cluster = evaluator.cluster.clone();
+pub fn dot_product_scheduler( evaluator: &Evaluator, task: PodSpec ) -> Option<SchedulingPick> {
+    let cluster = &evaluator.cluster;
 
-    // Filter and Score nodes
+    // Filter and Score nodes. Prefer not to run on model machines
     let nodes = cluster.filter_nodes( task.clone() );
     let score_func = | node_ref: NodeInfo | -> (NodeInfo, SCORE) {
         let node = node_ref.borrow();
-        let mut score : SCORE = SCORE::default();
-
-        score += (node.cpu_rem * task.cpu_milli) as SCORE;
-        score += (node.mem_rem * task.memory_mib) as SCORE;
-        score += (node.gpu_unallocated * task.gpu_milli ) as SCORE;
-
-        // Prefer not to run on model machines
-        if task.model.is_empty() && !node.spec.model.is_empty() {
-            score -= MODEL_PENALTY;
-        }
+        let score = DotProduct.score( evaluator, &task, &node ) - ModelPenalty.score( evaluator, &task, &node );
 
         (node_ref.clone(), score)
     };
 
-    let node_opt = nodes.score_by_max(score_func);
-
     // We have no nodes left to pick from!
-    if node_opt.is_none() { return None; }
-
-    let selected_node  = node_opt.unwrap();
+    let selected_node = nodes.score_by_max_with( score_func, &mut RandomTie(&mut *cluster.rng.borrow_mut()) )?;
     let node = selected_node.borrow().clone();
 
-    // Filter and Score GPUs
-    let gpus = node.filter_gpus(task.clone());
-    let gpus: Vec<GpuInfo> = if task.single_gpu() {
-        vec![gpus.score_by_min( |gpu| {
-                (gpu.clone(),gpu.borrow().gpu_milli as SCORE)
-            }).unwrap()]
-    } else {
-        gpus.take(task.num_gpu).collect()
-    };
+    let gpus = MostAllocatedGpu.pick( evaluator, &task, &node )?;
 
     Some((selected_node, gpus))
 }
 
 
 pub fn best_fit_scheduler( evaluator: &Evaluator, task: PodSpec ) -> Option<SchedulingPick> {
-    let task = task.to_owned();
-
-    let cluster = evaluator.cluster.clone();
+    let cluster = &evaluator.cluster;
 
-    // Filter and Score nodes
+    // Filter and Score nodes. Prefer not to run on model machines
     let nodes = cluster.filter_nodes( task.clone() );
     let score_func = | node_ref: NodeInfo | -> (NodeInfo, SCORE) {
         let node = node_ref.borrow();
-        let mut score : SCORE = SCORE::default();
-
-        score += (node.cpu_rem - task.cpu_milli) as SCORE;
-        score += (node.mem_rem - task.memory_mib) as SCORE;
-        score += (node.gpu_unallocated - task.gpu_milli ) as SCORE;
-
-        // Prefer not to run on model machines
-        if task.model.is_empty() && !node.spec.model.is_empty() {
-            score -= MODEL_PENALTY;
-        }
+        let score = BestFit.score( evaluator, &task, &node ) + ModelPenalty.score( evaluator, &task, &node );
 
         (node_ref.clone(), score)
     };
 
-    let node_opt = nodes.score_by_min(score_func);
+    // We have no nodes left to pick from!
+    let selected_node = nodes.score_by_min_with( score_func, &mut RandomTie(&mut *cluster.rng.borrow_mut()) )?;
+    let node = selected_node.borrow().clone();
+
+    let gpus = MostAllocatedGpu.pick( evaluator, &task, &node )?;
+
+    Some((selected_node, gpus))
+}
+
+
+// Fragmentation Gradient Descent (ATC'23): place the task where it grows fragmentation the least
+pub fn fgd_scheduler( evaluator: &Evaluator, task: PodSpec ) -> Option<SchedulingPick> {
+    let cluster = &evaluator.cluster;
+
+    // Filter nodes and look up precomputed fragmentation deltas
+    let nodes = cluster.filter_nodes( task.clone() )
+        .filter(|node_ref| FragFit.filter( evaluator, &task, &node_ref.borrow() ));
+    let score_func = | node_ref: NodeInfo | -> (NodeInfo, FRAG) {
+        let delta = cluster.frag_delta( &task, &node_ref.borrow() );
+
+        (node_ref, delta)
+    };
 
     // We have no nodes left to pick from!
-    if node_opt.is_none() { return None; }
+    let selected_node = nodes.score_by_min_with( score_func, &mut RandomTie(&mut *cluster.rng.borrow_mut()) )?;
+    let node = selected_node.borrow().clone();
+
+    // Recompute GPU choice for the selected node only
+    let gpus = FgdGpu.pick( evaluator, &task, &node )?;
 
-    let selected_node  = node_opt.unwrap();
+    Some((selected_node, gpus))
+}
+
+
+// GPU Packing: partially used GPUs first, then nodes with some GPUs in use, then idle nodes
+pub fn gpu_packing_scheduler( evaluator: &Evaluator, task: PodSpec ) -> Option<SchedulingPick> {
+    let cluster = &evaluator.cluster;
+
+    // Filter and Score nodes
+    let nodes = cluster.filter_nodes( task.clone() );
+    let score_func = | node_ref: NodeInfo | -> (NodeInfo, SCORE) {
+        let score = GpuPacking.score( evaluator, &task, &node_ref.borrow() );
+
+        (node_ref, score)
+    };
+
+    // We have no nodes left to pick from!
+    let selected_node = nodes.score_by_min_with( score_func, &mut RandomTie(&mut *cluster.rng.borrow_mut()) )?;
     let node = selected_node.borrow().clone();
 
-    // Filter and Score GPUs
-    let gpus = node.filter_gpus(task.clone());
-    let gpus: Vec<GpuInfo> = if task.single_gpu() {
-        vec![gpus.score_by_min( |gpu| {
-            (gpu.clone(),gpu.borrow().gpu_milli as SCORE)
-        }).unwrap()]
-    } else {
-        gpus.take(task.num_gpu).collect()
+    let gpus = MostAllocatedGpu.pick( evaluator, &task, &node )?;
+
+    Some((selected_node, gpus))
+}
+
+
+// GPU Clustering: keep GPU-sharing tasks of the same size together on the same GPUs
+pub fn gpu_clustering_scheduler( evaluator: &Evaluator, task: PodSpec ) -> Option<SchedulingPick> {
+    let cluster = &evaluator.cluster;
+
+    // Filter and Score nodes by their best GPU
+    let nodes = cluster.filter_nodes( task.clone() );
+    let score_func = | node_ref: NodeInfo | -> (NodeInfo, SCORE) {
+        let score = GpuClustering.score( evaluator, &task, &node_ref.borrow() );
+
+        (node_ref, score)
     };
 
+    // We have no nodes left to pick from!
+    let selected_node = nodes.score_by_min_with( score_func, &mut RandomTie(&mut *cluster.rng.borrow_mut()) )?;
+    let node = selected_node.borrow().clone();
+
+    let gpus = ClusteredGpu.pick( evaluator, &task, &node )?;
 
     Some((selected_node, gpus))
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::evaluator::workload::WorkloadMode;
+    use crate::heuristics::parse_decider;
+    use rstest::rstest;
+
+    // Evaluator over inline CSVs, to bind tasks by hand and ask schedulers for picks
+    fn evaluator( pod_csv: &str, node_csv: &str ) -> Evaluator {
+        seeded( 0, pod_csv, node_csv )
+    }
+
+    fn seeded( seed: SEED, pod_csv: &str, node_csv: &str ) -> Evaluator {
+        let scheduler = Box::new( FnScheduler::new( "random", random_scheduler ));
+        let decider = parse_decider("max_tasks_arrived=100").unwrap();
+
+        Evaluator::new( scheduler, decider, WorkloadMode::Sample, seed, pod_csv.as_bytes(), node_csv.as_bytes() ).unwrap()
+    }
+
+    // Binds the task to one GPU of the node. Returns the bind number
+    fn bind( eval: &Evaluator, task: &PodSpec, node: usize, gpu: usize ) -> usize {
+        let node = eval.cluster.nodes[node].clone();
+        let gpu = node.borrow().gpu_rem[gpu].clone();
+        eval.cluster.bind_task( task.clone(), (node, vec![gpu]) )
+    }
+
+    fn picked_node( pick: Option<SchedulingPick> ) -> usize {
+        picked( pick ).0
+    }
+
+    // Node id and GPU ids of the pick
+    fn picked( pick: Option<SchedulingPick> ) -> (usize, Vec<usize>) {
+        let (node, gpus) = pick.unwrap();
+        let id = node.borrow().spec.id;
+        (id, gpus.iter().map(|gpu| gpu.borrow().id ).collect())
+    }
+
+    #[rstest]
+    fn test_fgd() {
+        let eval = evaluator(
+            "name,cpu_milli,memory_mib,num_gpu,gpu_milli
+            big,32000,8192,1,400
+            half,4000,8192,1,500
+            whole,4000,8192,1,1000",
+            "sn,cpu_milli,memory_mib,gpu,model
+            node-0,96000,262144,1,V100M32
+            node-1,96000,262144,1,V100M32",
+        );
+        let [big, half, _] = eval.workload.tasks.clone().try_into().unwrap();
+
+        // Node 0 has 600 milli and less CPU left, node 1 has 500 milli left
+        bind( &eval, &big, 0, 0 );
+        bind( &eval, &half, 1, 0 );
+
+        // Best fit fills the node with the least left. FGD fills node 1's GPU exactly,
+        // where node 0 would keep a 100 milli sliver no task type can use
+        assert_eq!(picked_node( best_fit_scheduler( &eval, half.clone() )), 0);
+        assert_eq!(picked_node( fgd_scheduler( &eval, half.clone() )), 1);
+
+        let deltas: Vec<FRAG> = eval.cluster.nodes.iter()
+            .map(|node| eval.cluster.frag_delta( &half, &node.borrow() ))
+            .collect();
+        assert!(deltas[1] < deltas[0]);
+    }
+
+    #[rstest]
+    fn test_gpu_packing() {
+        let eval = evaluator(
+            "name,cpu_milli,memory_mib,num_gpu,gpu_milli
+            quarter,4000,8192,1,250
+            whole,4000,8192,1,1000",
+            "sn,cpu_milli,memory_mib,gpu,model
+            node-0,96000,262144,2,V100M32
+            node-1,96000,262144,2,V100M32
+            node-2,96000,262144,2,V100M32",
+        );
+        let [quarter, whole] = eval.workload.tasks.clone().try_into().unwrap();
+
+        // Node 0 is idle, node 1 has a GPU in use, node 2 a GPU partly in use
+        bind( &eval, &whole, 1, 0 );
+        bind( &eval, &quarter, 2, 0 );
+
+        // Shares go to the partly used GPU, even though node 1 has less left
+        assert_eq!(picked( gpu_packing_scheduler( &eval, quarter.clone() )), (2, vec![0]));
+
+        // Whole GPUs go to the node with GPUs in use and the least left, then to the other, before the idle node
+        assert_eq!(picked( gpu_packing_scheduler( &eval, whole.clone() )), (1, vec![1]));
+        bind( &eval, &whole, 1, 1 );
+        assert_eq!(picked( gpu_packing_scheduler( &eval, whole.clone() )), (2, vec![1]));
+    }
+
+    #[rstest]
+    fn test_gpu_clustering() {
+        let eval = evaluator(
+            "name,cpu_milli,memory_mib,num_gpu,gpu_milli
+            quarter,4000,8192,1,250
+            half,4000,8192,1,500",
+            "sn,cpu_milli,memory_mib,gpu,model
+            node-0,96000,262144,2,V100M32
+            node-1,96000,262144,2,V100M32",
+        );
+        let [quarter, half] = eval.workload.tasks.clone().try_into().unwrap();
+
+        bind( &eval, &half, 0, 0 );
+        bind( &eval, &quarter, 1, 0 );
+
+        // Tasks join a GPU holding their own size
+        assert_eq!(picked( gpu_clustering_scheduler( &eval, quarter.clone() )), (1, vec![0]));
+        assert_eq!(picked( gpu_clustering_scheduler( &eval, half.clone() )), (0, vec![0]));
+
+        // Once sizes mix on a GPU, an idle GPU is preferred over it
+        let mixed = bind( &eval, &half, 1, 0 );
+        assert_eq!(picked( gpu_clustering_scheduler( &eval, quarter.clone() )), (1, vec![1]));
+
+        // Until the other size leaves again
+        eval.cluster.unbind( half.id, mixed );
+        assert_eq!(eval.cluster.nodes[1].borrow().gpu_rem[0].borrow().share, quarter.gpu_milli);
+        assert_eq!(picked( gpu_clustering_scheduler( &eval, quarter.clone() )), (1, vec![0]));
+    }
+
+    #[rstest]
+    fn test_best_fit() {
+        let eval = evaluator(
+            "name,cpu_milli,memory_mib,num_gpu,gpu_milli
+            half,4000,8192,1,500",
+            "sn,cpu_milli,memory_mib,gpu,model
+            node-0,95000,262144,1,V100M32
+            node-1,96000,262144,1,",
+        );
+        let half = eval.workload.tasks[0].clone();
+
+        // Node 0 is the tighter fit, but tasks without a model stay off model machines when they can
+        assert_eq!(picked_node( best_fit_scheduler( &eval, half.clone() )), 1);
+    }
+
+    #[rstest]
+    fn test_ties() {
+        let pod_csv =
+            "name,cpu_milli,memory_mib,num_gpu,gpu_milli
+            half,4000,8192,1,500";
+        let node_csv =
+            "sn,cpu_milli,memory_mib,gpu,model
+            node-0,96000,262144,1,V100M32
+            node-1,96000,262144,1,V100M32
+            node-2,96000,262144,1,V100M32
+            node-3,96000,262144,1,V100M32";
+
+        let schedulers: [ScheduleFunc; 5] = [dot_product_scheduler, best_fit_scheduler, fgd_scheduler,
+                                             gpu_packing_scheduler, gpu_clustering_scheduler];
+
+        // Every node is the same, so every node is a tie. The pick follows the seed, not the node list
+        for scheduler in schedulers {
+            let picks: Vec<usize> = (0..20)
+                .map(|seed| {
+                    let eval = seeded( seed, pod_csv, node_csv );
+                    let half = eval.workload.tasks[0].clone();
+                    picked_node( scheduler( &eval, half ))
+                })
+                .collect();
+
+            assert!(picks.iter().any(|&node| node != 0 ));
+            assert!(picks.iter().any(|&node| node != picks[0] ));
+
+            let eval = seeded( 7, pod_csv, node_csv );
+            let half = eval.workload.tasks[0].clone();
+            assert_eq!(picked_node( scheduler( &eval, half )), picks[7]);
+        }
+    }
+}
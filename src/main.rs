@@ -1,29 +1,104 @@
 #[macro_use]
 extern crate public;
 
+mod cli;
 mod csv_reader;
+mod error;
 mod types;
 mod evaluator;
 mod heuristics;
+mod mig;
+mod sweep;
 
-use std::fs::File;
+use std::process::ExitCode;
+use clap::Parser;
 use heuristics::*;
 
+use crate::cli::{Args, Command};
+use crate::error::{write_error, Error, Result};
 use crate::evaluator::*;
-use crate::heuristics::simple_schedulers::*;
+use crate::evaluator::report::{Report, Summary};
+use crate::types::*;
 
-fn main() {
+fn main() -> ExitCode {
+    match try_main() {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(err) => {
+            eprintln!("Error: {}", err);
+            ExitCode::FAILURE
+        },
+    }
+}
 
-    let node_csv = File::open(&"clusterdata/node_data/all_nodes.csv").expect("node file not found");
-    let pod_csv = File::open(&"clusterdata/pod_data/default.csv").expect("pod file not found");
+fn try_main() -> Result<()> {
+
+    let args = Args::parse();
+    let seed = args.seed.unwrap_or_else(rand::random);
+
+    match &args.command {
+        None => run(&args, seed),
+        Some(Command::Sweep(sweep_args)) => {
+            let summaries = sweep::sweep(&args, sweep_args, seed)?;
+            sweep::print_table(&summaries, args.format, &mut std::io::stdout())?;
+
+            if let Some(dir) = &args.output {
+                error::create_dir(dir)?;
+                sweep::write_table(&summaries, dir)?;
+            }
+
+            Ok(())
+        },
+    }
+}
+
+fn run( args: &Args, seed: SEED ) -> Result<()> {
+
+    let node_csv = error::open(&args.nodes)?;
+    let pod_csv = error::open(&args.pods)?;
+
+    let scheduler = parse_scheduler(&args.scheduler).map_err(Error::Spec)?;
+    let decider = parse_decider(&args.decider).map_err(Error::Spec)?;
 
     let mut eval: Evaluator = Evaluator::new(
-        best_fit_scheduler,
-        max_tasks_arrived,
+        scheduler,
+        decider,
+        args.mode(),
+        seed,
         pod_csv,
         node_csv,
-    );
+    )?
+    .with_preemption(args.preempt)
+    .with_strict(args.strict)
+    .with_export(args.export.clone());
+
+    if let Some(dir) = &args.export {
+        error::create_dir(dir)?;
+    }
+
+    let batches = eval.evaluate(args.batches, args.format)?;
+
+    let summary = Summary {
+        scheduler: eval.scheduler_name(),
+        decider: eval.decider_name(),
+        trace: args.pods.clone(),
+        nodes: args.nodes.clone(),
+        seed,
+        ..Default::default()
+    };
+    let report = Report::new(summary, batches);
+
+    match args.format {
+        OutputFormat::Text => print!("{}", report),
+        OutputFormat::Csv => {},
+        OutputFormat::Json => println!("{}", serde_json::to_string_pretty(&report).map_err(write_error)?),
+    }
+
+    if let Some(dir) = &args.output {
+        error::create_dir(dir)?;
 
-    eval.evaluate()
+        report.write_csv(dir)?;
+        report.write_json(dir)?;
+    }
 
-}
\ No newline at end of file
+    Ok(())
+}
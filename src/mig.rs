@@ -0,0 +1,181 @@
+use crate::types::*;
+
+// Multi-Instance GPU: A100 and H100 GPUs split into 7 compute slices, which are only handed out
+// as fixed profiles at fixed offsets, each with a fixed part of the GPU's memory. A task gets the
+// smallest profile that covers its share and memory and holds all of it, and free slices that no
+// profile can reach are stranded
+
+pub const MIG_SLICES: NUM = 7;
+
+// Profile memory is counted in eighths of the GPU's
+const MIG_MEMORY: NUM = 8;
+
+// Profiles by size in slices, their memory in eighths, and the slices each may start at.
+// On an A100 these are 1g.5gb, 2g.10gb, 3g.20gb, 4g.20gb and 7g.40gb, and twice the memory on an H100
+const PROFILES: [(NUM, NUM, &[NUM]); 5] = [
+    (1, 1, &[0, 1, 2, 3, 4, 5, 6]),
+    (2, 2, &[0, 2, 4]),
+    (3, 4, &[0, 4]),
+    (4, 4, &[0]),
+    (7, 8, &[0]),
+];
+
+fn memory( size: NUM ) -> NUM {
+    PROFILES.iter()
+        .find(|&&(s, _, _)| s == size )
+        .map_or(0, |&(_, memory, _)| memory)
+}
+
+// Smallest profile, in slices, that covers the share and the memory on a GPU with `capacity` MiB.
+// None if it is more than a GPU
+pub fn profile( gpu_milli: GPU, gpu_memory_mib: MEM, capacity: MEM ) -> Option<NUM> {
+    PROFILES.iter()
+        .find(|&&(size, memory, _)| {
+            gpu_milli * MIG_SLICES as GPU <= size as GPU * GPU_MILLI &&
+                gpu_memory_mib * MIG_MEMORY as MEM <= memory as MEM * capacity
+        })
+        .map(|&(size, _, _)| size)
+}
+
+// Milli where the slice starts. Profiles are charged between their boundaries,
+// so a fully partitioned GPU still adds up to exactly GPU_MILLI
+fn boundary( slice: NUM ) -> GPU {
+    slice as GPU * GPU_MILLI / MIG_SLICES as GPU
+}
+
+// Where a profile instance sits on its GPU
+#[derive(Debug, Clone, Copy)]
+#[derive(PartialEq, Eq, Hash)]
+#[derive(serde::Serialize)]
+#[public]
+struct Slot {
+    start: NUM,
+    size: NUM,
+}
+
+impl Slot {
+    fn mask(&self) -> u8 { (((1u16 << self.size) - 1) as u8) << self.start }
+
+    // What the instance holds of its GPU
+    pub fn gpu_milli(&self) -> GPU { boundary(self.start + self.size) - boundary(self.start) }
+
+    // Memory of the instance, on a GPU with `capacity` MiB
+    pub fn gpu_memory_mib(&self, capacity: MEM ) -> MEM { capacity * memory(self.size) as MEM / MIG_MEMORY as MEM }
+}
+
+impl std::fmt::Display for Slot {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}g@{}", self.size, self.start )
+    }
+}
+
+// Slices in use on one GPU, a bit each
+#[derive(Debug, Clone, Copy, Default)]
+#[derive(PartialEq, Eq, Hash)]
+pub struct Slices(u8);
+
+impl Slices {
+    pub const FULL: Slices = Slices((1 << MIG_SLICES) - 1);
+
+    fn is_free(&self, slot: Slot ) -> bool { self.0 & slot.mask() == 0 }
+
+    // Legal slots of the profile that are free now
+    fn slots(self, size: NUM ) -> impl Iterator<Item=Slot> {
+        PROFILES.iter()
+            .filter(move |&&(s, _, _)| s == size )
+            .flat_map(|&(size, _, starts)| starts.iter().map(move |&start| Slot { start, size }))
+            .filter(move |&slot| self.is_free(slot) )
+    }
+
+    // Largest profile that still fits, in slices. 0 if none does
+    fn largest(&self) -> NUM {
+        PROFILES.iter().rev()
+            .map(|&(size, _, _)| size)
+            .find(|&size| self.slots(size).next().is_some() )
+            .unwrap_or(0)
+    }
+
+    // Share of the largest profile that still fits. Free slices beyond it are stranded for larger tasks
+    pub fn usable(&self) -> GPU {
+        match self.largest() {
+            MIG_SLICES => GPU_MILLI,
+            size => size as GPU * GPU_MILLI / MIG_SLICES as GPU,
+        }
+    }
+
+    // Where a task with the share and memory would go: the free slot of its profile that keeps the largest
+    // profile placeable afterwards, the highest of those so small instances pack away from offset 0
+    pub fn slot(&self, gpu_milli: GPU, gpu_memory_mib: MEM, capacity: MEM ) -> Option<Slot> {
+        self.slots( profile(gpu_milli, gpu_memory_mib, capacity)? )
+            .max_by_key(|&slot| (self.claim(slot).largest(), slot.start) )
+    }
+
+    pub fn claim(&self, slot: Slot ) -> Slices { Slices(self.0 | slot.mask()) }
+
+    pub fn release(&self, slot: Slot ) -> Slices { Slices(self.0 & !slot.mask()) }
+}
+
+impl std::fmt::Display for Slices {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        (0..MIG_SLICES).try_for_each(|slice| write!(f, "{}", if self.0 & (1 << slice) == 0 { '.' } else { '#' }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::rstest;
+
+    const A100: MEM = 40 * MEM_MIB;
+
+    #[rstest]
+    #[case(1, 0, Some(1))]
+    #[case(142, 0, Some(1))]
+    #[case(143, 0, Some(2))]
+    #[case(500, 0, Some(4))]
+    #[case(700, 0, Some(7))]
+    #[case(1000, 0, Some(7))]
+    #[case(1001, 0, None)]
+    // Memory can call for a larger profile than the share: 1g.5gb, 2g.10gb, 3g.20gb and 7g.40gb
+    #[case(100, 5 * MEM_MIB, Some(1))]
+    #[case(100, 6 * MEM_MIB, Some(2))]
+    #[case(100, 16 * MEM_MIB, Some(3))]
+    #[case(500, 16 * MEM_MIB, Some(4))]
+    #[case(100, 24 * MEM_MIB, Some(7))]
+    #[case(100, 48 * MEM_MIB, None)]
+    fn test_profile( #[case] gpu_milli: GPU, #[case] gpu_memory_mib: MEM, #[case] size: Option<NUM> ) {
+        assert_eq!(profile( gpu_milli, gpu_memory_mib, A100 ), size);
+    }
+
+    #[rstest]
+    fn test_layout() {
+        // Small instances pack from the top, keeping the 4g slot at offset 0
+        let mut slices = Slices::default();
+        for start in [6, 5, 4] {
+            let slot = slices.slot( 100, 0, A100 ).unwrap();
+            assert_eq!(slot, Slot { start, size: 1 });
+            slices = slices.claim( slot );
+        }
+        assert_eq!(slices.to_string(), "....###");
+        assert_eq!(slices.slot( 500, 0, A100 ), Some(Slot { start: 0, size: 4 }));
+
+        // 1g instances at offsets 1 and 4 leave 5 slices free, but no 3g slot among them
+        let slices = Slices::default()
+            .claim( Slot { start: 1, size: 1 } )
+            .claim( Slot { start: 4, size: 1 } );
+        assert_eq!(slices.usable(), 2 * GPU_MILLI / 7);
+        assert_eq!(slices.slot( 400, 0, A100 ), None);
+        assert_eq!(slices.slot( 200, 0, A100 ), Some(Slot { start: 2, size: 2 }));
+
+        // A fully partitioned GPU is charged exactly once
+        let milli: GPU = (0..MIG_SLICES).map(|start| Slot { start, size: 1 }.gpu_milli() ).sum();
+        assert_eq!(milli, GPU_MILLI);
+        assert_eq!(Slices::FULL.usable(), 0);
+        assert_eq!(Slices::default().usable(), GPU_MILLI);
+
+        // Instances hold their profile's memory, 3g and 4g alike
+        assert_eq!(Slot { start: 4, size: 3 }.gpu_memory_mib( A100 ), 20 * MEM_MIB);
+        assert_eq!(Slot { start: 0, size: 4 }.gpu_memory_mib( A100 ), 20 * MEM_MIB);
+        assert_eq!(Slot { start: 0, size: 7 }.gpu_memory_mib( 80 * MEM_MIB ), 80 * MEM_MIB);
+    }
+}
@@ -0,0 +1,200 @@
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use crate::cli::{Args, SweepArgs};
+use crate::error::{self, write_error, Error, Result};
+use crate::evaluator::report::{Report, Summary};
+use crate::evaluator::*;
+use crate::heuristics::*;
+use crate::types::*;
+
+// Comparison grid: every scheduler on every pod trace and node list.
+// All cells share the seed, so schedulers on the same trace see the same task sequence.
+pub fn sweep( args: &Args, sweep_args: &SweepArgs, seed: SEED ) -> Result<Vec<Summary>> {
+    let node_files = input_files( &sweep_args.node_dir )?;
+    let pod_files = input_files( &sweep_args.pod_dir )?;
+
+    let schedulers: Vec<&str> = if sweep_args.schedulers.is_empty() {
+        SCHEDULERS.iter().map(|&(name, _)| name).collect()
+    } else {
+        sweep_args.schedulers.iter().map(String::as_str).collect()
+    };
+
+    let mut summaries = Vec::with_capacity(node_files.len() * pod_files.len() * schedulers.len());
+
+    for node_file in &node_files {
+        for pod_file in &pod_files {
+            for &name in &schedulers {
+
+                let scheduler = parse_scheduler( name ).map_err(Error::Spec)?;
+                let decider = parse_decider( &args.decider ).map_err(Error::Spec)?;
+                let summary = Summary {
+                    scheduler: scheduler.name().to_string(),
+                    decider: decider.name(),
+                    trace: file_stem(pod_file),
+                    nodes: file_stem(node_file),
+                    seed,
+                    ..Default::default()
+                };
+
+                let eval = Evaluator::new(
+                    scheduler,
+                    decider,
+                    args.mode(),
+                    seed,
+                    error::open(pod_file)?,
+                    error::open(node_file)?,
+                );
+
+                // Traces the mode cannot run, such as replaying one without a timeline, are left out of the grid
+                // and listed as skipped, rather than ending the sweep
+                let eval = match eval {
+                    Err(err @ Error::NoTimeline { .. }) => {
+                        eprintln!("{} / {} / {}: skipped, {}", summary.nodes, summary.trace, name, err);
+
+                        summaries.push( Summary { skipped: Some(err.to_string()), ..summary } );
+                        continue;
+                    },
+                    eval => eval?,
+                };
+
+                // Pipeline specs such as "dot_product*0.7+best_fit*0.3" are not friendly file names
+                let export = args.export.as_ref()
+                    .map(|dir| dir.join(format!("{}_{}_{}", file_stem(node_file), file_stem(pod_file),
+                                                name.replace(|c: char| !c.is_alphanumeric() && c != '_' && c != '.', "-"))));
+                if let Some(dir) = &export {
+                    error::create_dir(dir)?;
+                }
+
+                let mut eval = eval
+                    .with_preemption( args.preempt )
+                    .with_strict( args.strict )
+                    .with_export( export );
+
+                let batches = (0..args.batches)
+                    .map(|batch_num| eval.run_batch(batch_num))
+                    .collect::<Result<_>>()?;
+
+                let report = Report::new( summary, batches );
+
+                // Progress goes to stderr, so stdout stays a clean table
+                eprintln!("{} / {} / {}: {:.2}% allocated",
+                          report.summary.nodes, report.summary.trace, name, report.summary.avg_alloc_rate * 100.0);
+
+                summaries.push( report.summary );
+            }
+        }
+    }
+
+    Ok(summaries)
+}
+
+pub fn print_table( summaries: &[Summary], format: OutputFormat, out: &mut impl Write ) -> Result<()> {
+    match format {
+        OutputFormat::Text => {
+            writeln!(out, "{: <16}{: <16}{: <16}{: >12}{: >12}{: >12}",
+                     "nodes", "trace", "scheduler", "scheduled", "alloc %", "frag %").map_err(Error::Write)?;
+
+            summaries.iter().try_for_each(|s| match &s.skipped {
+                Some(reason) => writeln!(out, "{: <16}{: <16}{: <16}  skipped: {}",
+                                         s.nodes, s.trace, s.scheduler, reason),
+                None => writeln!(out, "{: <16}{: <16}{: <16}{: >12.0}{: >12.2}{: >12.2}",
+                                 s.nodes, s.trace, s.scheduler,
+                                 s.avg_tasks_scheduled, s.avg_alloc_rate * 100.0, s.avg_frag_rate * 100.0),
+            }).map_err(Error::Write)?;
+        },
+        OutputFormat::Csv => {
+            let mut wtr = csv::Writer::from_writer( out );
+            summaries.iter().try_for_each(|s| wtr.serialize(s)).map_err(write_error)?;
+            wtr.flush().map_err(Error::Write)?;
+        },
+        OutputFormat::Json => {
+            let json = serde_json::to_string_pretty( summaries ).map_err(write_error)?;
+            writeln!(out, "{}", json).map_err(Error::Write)?;
+        },
+    }
+
+    Ok(())
+}
+
+// comparison.csv and comparison.json
+pub fn write_table( summaries: &[Summary], dir: &Path ) -> Result<()> {
+    let mut wtr = csv::Writer::from_writer( error::create( dir.join("comparison.csv") )? );
+    summaries.iter().try_for_each(|s| wtr.serialize(s)).map_err(write_error)?;
+    wtr.flush().map_err(Error::Write)?;
+
+    let file = error::create( dir.join("comparison.json") )?;
+    serde_json::to_writer_pretty( file, summaries ).map_err(write_error)
+}
+
+// CSV files and YAML manifests in a directory, in name order so the table is stable
+fn input_files( dir: &Path ) -> Result<Vec<PathBuf>> {
+    let open_error = |source| Error::Open { path: dir.to_path_buf(), source };
+    let mut files: Vec<PathBuf> = std::fs::read_dir(dir).map_err(open_error)?
+        .map(|entry| entry.map(|e| e.path()))
+        .collect::<Result<_, _>>().map_err(open_error)?;
+
+    files.retain(|path| path.extension().is_some_and(|ext| ext == "csv" || ext == "yaml" || ext == "yml"));
+    files.sort();
+
+    Ok(files)
+}
+
+fn file_stem( path: &Path ) -> String {
+    path.file_stem().map(|s| s.to_string_lossy().into_owned()).unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cli::Command;
+    use clap::Parser;
+    use std::fs;
+
+    #[test]
+    fn test_sweep() {
+        // Own directory per process, so concurrent test runs do not share files
+        let dir = std::env::temp_dir().join(format!("gpu_scheduler_sim_{}_test_sweep", std::process::id()));
+        let (pod_dir, node_dir) = (dir.join("pods"), dir.join("nodes"));
+        fs::create_dir_all(&pod_dir).unwrap();
+        fs::create_dir_all(&node_dir).unwrap();
+
+        fs::write(node_dir.join("small.csv"), "sn,cpu_milli,memory_mib,gpu,model\nnode-0,96000,262144,2,V100M32\n").unwrap();
+        fs::write(pod_dir.join("timed.csv"), "name,cpu_milli,memory_mib,num_gpu,gpu_milli,creation_time,deletion_time\n\
+                                              a,4000,8192,1,500,0,10\nb,4000,8192,1,1000,5,20\n").unwrap();
+        fs::write(pod_dir.join("untimed.csv"), "name,cpu_milli,memory_mib,num_gpu,gpu_milli\na,4000,8192,1,500\n").unwrap();
+        fs::write(pod_dir.join("notes.txt"), "not a trace").unwrap();
+
+        let args = Args::parse_from([
+            "gpu_scheduler_sim", "--replay", "--batches", "1", "--decider", "max_tasks_arrived=10",
+            "sweep", "--schedulers", "best_fit,fgd",
+            "--pod-dir", pod_dir.to_str().unwrap(), "--node-dir", node_dir.to_str().unwrap(),
+        ]);
+        let Some(Command::Sweep(sweep_args)) = &args.command else { panic!("sweep subcommand not parsed") };
+
+        // Replay cannot run the trace without a timeline, but the rest of the grid still runs
+        let summaries = sweep( &args, sweep_args, 0 ).unwrap();
+        let cells: Vec<(&str, &str, bool)> = summaries.iter()
+            .map(|s| (s.trace.as_str(), s.scheduler.as_str(), s.skipped.is_some()))
+            .collect();
+        assert_eq!(cells, [("timed", "best_fit", false), ("timed", "fgd", false), ("untimed", "best_fit", true), ("untimed", "fgd", true)]);
+        assert_eq!(summaries[0].avg_tasks_scheduled, 2.0);
+
+        let mut table = Vec::new();
+        print_table( &summaries, OutputFormat::Text, &mut table ).unwrap();
+        let table = String::from_utf8(table).unwrap();
+
+        assert_eq!(table.lines().count(), 5);
+        assert!(table.lines().next().unwrap().starts_with("nodes"));
+        assert!(table.lines().nth(1).unwrap().starts_with("small           timed           best_fit"));
+        assert!(table.lines().nth(3).unwrap().contains("skipped: "));
+
+        let mut csv = Vec::new();
+        print_table( &summaries, OutputFormat::Csv, &mut csv ).unwrap();
+        let csv = String::from_utf8(csv).unwrap();
+        assert!(csv.starts_with("scheduler,decider,trace,nodes,seed,skipped,"));
+        assert_eq!(csv.lines().count(), 5);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -1,9 +1,19 @@
+#![allow(clippy::upper_case_acronyms)]
+
 use std::cell::RefCell;
+use std::collections::HashSet;
 use std::rc::Rc;
 use bitflags::bitflags;
 use crate::evaluator::Evaluator;
+use crate::mig::{Slices, Slot};
+
+// Signed, so penalties can take a score below zero
+pub type SCORE = i128;
+
+// Weight of a score in a weighted combination. Negative weights prefer lower scores
+pub type WEIGHT = f64;
 
-pub type SCORE = u128;
+pub type SEED = u64;
 
 pub type NODE = usize;
 pub type POD = usize;
@@ -14,8 +24,14 @@ pub type MEM = u64;
 pub type NUM = usize;
 pub type GPU = u64;
 
+// Change in fragmentation, in GPU milli. Negative when a placement removes fragments
+pub type FRAG = i64;
+
 pub type MODEL = GpuSpec;
 
+// Trace timestamps, in seconds
+pub type TIME = u64;
+
 pub const GPU_MILLI : GPU = 1000;
 pub const MEM_MIB : MEM = 1024;
 pub const CPU_MILLI : CPU = 1000;
@@ -24,8 +40,114 @@ pub const CPU_MILLI : CPU = 1000;
 pub type SchedulingPick = (NodeInfo, Vec<GpuInfo>);
 pub type ScheduleFunc = fn(evaluator: &Evaluator, task: PodSpec ) -> Option<SchedulingPick>;
 
+// Scheduler with its own state. Evaluator notifies it of binds and deployments
+pub trait Scheduler {
+    fn name(&self) -> &str;
+
+    fn schedule(&mut self, evaluator: &Evaluator, task: PodSpec ) -> Option<SchedulingPick>;
+
+    // Called after the pick has been applied to the cluster
+    fn on_bind(&mut self, _evaluator: &Evaluator, _task: &PodSpec, _pick: &SchedulingPick ) {}
+
+    // Called after the batch was deployed and the cluster reset
+    fn on_deploy(&mut self, _evaluator: &Evaluator ) {}
+
+    // Whether the scheduler would place the task on the node if it had room.
+    // Preemption only evicts tasks from nodes the scheduler admits
+    fn admits(&self, _evaluator: &Evaluator, _task: &PodSpecStruct, _node: &NodeInfoStruct ) -> bool { true }
+}
+pub type BoxedScheduler = Box<dyn Scheduler>;
+
+// Adapts a stateless function or closure to the Scheduler trait
+pub struct FnScheduler<F> {
+    name: String,
+    func: F,
+}
+
+impl<F> FnScheduler<F>
+where
+    F: FnMut(&Evaluator, PodSpec) -> Option<SchedulingPick>,
+{
+    pub fn new( name: &str, func: F ) -> Self {
+        Self { name: name.to_string(), func }
+    }
+}
+
+impl<F> Scheduler for FnScheduler<F>
+where
+    F: FnMut(&Evaluator, PodSpec) -> Option<SchedulingPick>,
+{
+    fn name(&self) -> &str { &self.name }
+
+    fn schedule(&mut self, evaluator: &Evaluator, task: PodSpec ) -> Option<SchedulingPick> {
+        (self.func)(evaluator, task)
+    }
+}
+
 // Decides when to deploy workload instead of waiting for more tasks
-pub type DeployFunc = fn(evaluator: &Evaluator ) -> bool;
+pub trait Decider {
+    // Includes the configured thresholds, so runs can be told apart
+    fn name(&self) -> String;
+
+    // Asked after every task
+    fn deploy(&mut self, evaluator: &Evaluator ) -> bool;
+
+    // Called after the batch was deployed and the cluster reset
+    fn on_deploy(&mut self, _evaluator: &Evaluator ) {}
+}
+pub type BoxedDecider = Box<dyn Decider>;
+
+// Pod or node name from the trace, such as openb-pod-0017. Interned, so the many copies
+// of a spec made while sampling share one string. Keys carry the empty name, which is kept
+// on hand so that key() only bumps a reference count instead of looking it up
+#[derive(Debug, Clone)]
+#[derive(PartialEq, Eq, Hash)]
+#[derive(PartialOrd, Ord)]
+pub struct Name(Rc<str>);
+
+thread_local! {
+    static NAMES: RefCell<HashSet<Rc<str>>> = RefCell::new(HashSet::new());
+    static EMPTY: Name = Name(Rc::from(""));
+}
+
+impl Name {
+    pub fn new( name: &str ) -> Self {
+        NAMES.with_borrow_mut(|names| {
+            if let Some(interned) = names.get(name) {
+                return Name(interned.clone());
+            }
+            let interned: Rc<str> = Rc::from(name);
+            names.insert(interned.clone());
+            Name(interned)
+        })
+    }
+
+    pub fn as_str(&self) -> &str { &self.0 }
+
+    pub fn is_empty(&self) -> bool { self.0.is_empty() }
+}
+
+impl Default for Name {
+    fn default() -> Self { EMPTY.with(Name::clone) }
+}
+
+impl std::fmt::Display for Name {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.pad(&self.0)
+    }
+}
+
+impl<'de> serde::Deserialize<'de> for Name {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error> where D: serde::Deserializer<'de> {
+        String::deserialize(deserializer).map(|name| Name::new( name.trim() ))
+    }
+}
+
+impl serde::Serialize for Name {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error> where S: serde::Serializer {
+        serializer.serialize_str(&self.0)
+    }
+}
 
 #[derive(Debug, Clone)]
 #[derive(serde::Deserialize)]
@@ -42,9 +164,29 @@ bitflags! {
         const T4 = 16;
         const V100M16 = 32;
         const V100M32 = 64;
+        // Partitioned with MIG
+        const A100 = 128;
+        const H100 = 256;
     }
 }
 
+// Kubernetes QoS class of a pod, highest priority first.
+// Pods in traces without a qos column are Burstable, so preemption never touches them
+#[derive(Debug, Clone, Copy, Default)]
+#[derive(serde::Deserialize, serde::Serialize)]
+#[derive(PartialEq, Eq, Hash)]
+#[derive(PartialOrd, Ord)]
+pub enum Qos {
+    // Latency-sensitive
+    LS,
+    // The few Guaranteed pods in the traces neither preempt nor get preempted either
+    #[default]
+    #[serde(alias = "Guaranteed")]
+    Burstable,
+    // Best effort
+    BE,
+}
+
 #[derive(Debug, Clone)]
 #[derive(serde::Deserialize)]
 #[derive(PartialEq, Eq)]
@@ -53,22 +195,56 @@ bitflags! {
 struct PodSpecStruct {
     #[serde(skip)]
     id: POD,
+    // Readers name unnamed pods after their id
+    #[serde(default)]
+    name: Name,
 
     // Data
     cpu_milli: CPU,
     memory_mib: MEM,
     num_gpu: NUM,
     gpu_milli: GPU,
+    // GPU memory needed on each GPU. 0 if the pod does not ask for any
+    #[serde(default)]
+    gpu_memory_mib: MEM,
     #[serde(rename = "gpu_spec")]
     #[serde(deserialize_with = "crate::csv_reader::parse_gpu_spec")]
     #[serde(default)]
-    model: MODEL
+    model: MODEL,
+    #[serde(default)]
+    qos: Qos,
+
+    // Trace timeline, used when replaying
+    #[serde(default)]
+    creation_time: TIME,
+    #[serde(default)]
+    deletion_time: TIME,
 }
 pub type PodSpec = Rc<PodSpecStruct>;
 pub type PodSpecKey = PodSpecStruct;
 
 impl PodSpecStruct {
     pub fn single_gpu(&self) -> bool { self.num_gpu == 1 }
+
+    // GPU milli needed on each GPU. Multi-GPU tasks need whole GPUs
+    pub fn per_gpu_milli(&self) -> GPU {
+        if self.single_gpu() { self.gpu_milli } else { self.gpu_milli.min(GPU_MILLI) }
+    }
+
+    // Tasks with identical resource requests share a key, regardless of id, name, QoS and timeline
+    pub fn key(&self) -> PodSpecKey {
+        PodSpecKey {
+            id: POD::default(),
+            name: Name::default(),
+            qos: Qos::default(),
+            creation_time: TIME::default(),
+            deletion_time: TIME::default(),
+            ..self.clone()
+        }
+    }
+
+    // How long the task held its resources in the trace
+    pub fn duration(&self) -> TIME { self.deletion_time.saturating_sub(self.creation_time) }
 }
 
 
@@ -79,6 +255,10 @@ impl PodSpecStruct {
 struct NodeSpecStruct {
     #[serde(skip)]
     id: NODE,
+    // Readers name unnamed nodes after their id
+    #[serde(rename = "sn")]
+    #[serde(default)]
+    name: Name,
 
     // Data
     cpu_milli: CPU,
@@ -107,33 +287,120 @@ struct NodeInfoStruct {
 
     gpu_unallocated: GPU,
     gpu_frag: GPU,
+
+    // Tasks bound here, in bind order
+    pods: Vec<Placement>,
 }
 pub type NodeInfo = Rc<RefCell<NodeInfoStruct>>;
 
+// Where a bound task runs: its node, GPUs, and the milli it holds on each of them.
+// `bind` numbers the binds of the batch, since sampled workloads can bind the same pod more than once
+#[derive(Debug, Clone)]
+#[derive(PartialEq, Eq)]
+#[derive(serde::Serialize)]
+#[public]
+struct Placement {
+    pod: POD,
+    pod_name: Name,
+    bind: usize,
+    node: NODE,
+    node_name: Name,
+    gpus: Vec<NUM>,
+    gpu_milli: GPU,
+    gpu_memory_mib: MEM,
+    // MIG instance of a share of one GPU
+    slot: Option<Slot>,
+}
+
 #[derive(Debug, Clone)]
 #[public]
 struct GpuInfoStruct {
     id: NUM,
     gpu_milli: GPU,
+
+    // GPU share of the tasks bound here: 0 while idle, GPU::MAX once different sizes are mixed
+    share: GPU,
+
+    // MIG slices in use, for GPUs partitioned with MIG
+    mig: Option<Slices>,
+
+    // GPU memory left and in all, for models whose memory is known
+    gpu_memory_mib: Option<MEM>,
+    gpu_memory_total: Option<MEM>,
 }
 pub type GpuInfo = Rc<RefCell<GpuInfoStruct>>;
 
 
+impl GpuSpec {
+    // GPUs that can only be shared as MIG instances
+    pub fn mig(&self) -> bool { self.intersects( GpuSpec::A100 | GpuSpec::H100 ) }
+
+    // Memory of each GPU of the model. None for the undisclosed G2 and G3, whose memory is not capped
+    pub fn gpu_memory_mib(&self) -> Option<MEM> {
+        const GIB: [(GpuSpec, MEM); 7] = [
+            (GpuSpec::A10, 24),
+            (GpuSpec::P100, 16),
+            (GpuSpec::T4, 16),
+            (GpuSpec::V100M16, 16),
+            (GpuSpec::V100M32, 32),
+            (GpuSpec::A100, 40),
+            (GpuSpec::H100, 80),
+        ];
+
+        GIB.iter()
+            .find(|(model, _)| model == self )
+            .map(|&(_, gib)| gib * MEM_MIB )
+    }
+}
+
+impl GpuInfoStruct {
+    // Largest share a task could still get here. Short of gpu_milli on MIG GPUs with stranded slices
+    pub fn usable(&self) -> GPU {
+        self.mig.map_or(self.gpu_milli, |slices| slices.usable())
+    }
+
+    // Free MIG instance the task's per-GPU share and memory would get here. None on other GPUs
+    pub fn slot(&self, task: &PodSpecStruct ) -> Option<Slot> {
+        self.mig?.slot( task.per_gpu_milli(), task.gpu_memory_mib, self.gpu_memory_total? )
+    }
+
+    // Whether a task could get its per-GPU share and memory here, and an instance for both on MIG GPUs
+    pub fn fits(&self, task: &PodSpecStruct ) -> bool {
+        task.per_gpu_milli() <= self.usable() &&
+            self.gpu_memory_mib.is_none_or(|gpu_memory_mib| task.gpu_memory_mib <= gpu_memory_mib ) &&
+            (self.mig.is_none() || self.slot( task ).is_some())
+    }
+}
+
 impl std::fmt::Display for GpuSpec {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         bitflags::parser::to_writer(self, f)
     }
 }
 
+impl Qos {
+    // Latency-sensitive pods may evict best-effort pods to make room
+    pub fn preempts(&self) -> bool { *self == Qos::LS }
+
+    pub fn preemptible(&self) -> bool { *self == Qos::BE }
+}
+
+impl std::fmt::Display for Qos {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:?}", self)
+    }
+}
+
 impl std::fmt::Display for NodeSpecStruct {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{: >4.1} cpu\t{: >4.1} GiB\t{: >4.1} GPU\t{: <4}",
+        write!(f, "{: <16}\t{: >4.1} cpu\t{: >4.1} GiB\t{: >4.1} GPU\t{: <4}",
+               self.name,
                self.cpu_milli as f64 / CPU_MILLI as f64,
                self.memory_mib as f64 / MEM_MIB as f64,
                self.num_gpu as f64,
                match &self.model {
-                   GpuSpec(0) => "_",
-                   model => &model.to_string(),
+                   GpuSpec(0) => String::from("_"),
+                   model => model.to_string(),
                },
         )
     }
@@ -141,7 +408,8 @@ impl std::fmt::Display for NodeSpecStruct {
 
 impl std::fmt::Display for PodSpecStruct {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{: >4.1} cpu\t{: >4.1} GiB\t{: >4.1} GPU\t{: <4}",
+        write!(f, "{: <16}\t{: >4.1} cpu\t{: >4.1} GiB\t{: >4.1} GPU\t{: <4}",
+               self.name,
                self.cpu_milli as f64 / CPU_MILLI as f64,
                self.memory_mib as f64 / MEM_MIB as f64,
                if self.single_gpu() {
@@ -150,8 +418,8 @@ impl std::fmt::Display for PodSpecStruct {
                    self.num_gpu as f64
                },
                match &self.model {
-                   GpuSpec(0) => "_",
-                   model => &model.to_string(),
+                   GpuSpec(0) => String::from("_"),
+                   model => model.to_string(),
                },
         )
     }
@@ -168,6 +436,9 @@ impl std::fmt::Display for NodeInfoStruct {
         )?;
 
         let write_gpu = | gpu: &GpuInfo | -> std::fmt::Result {
+            if let Some(slices) = gpu.borrow().mig {
+                return write!(f, "[\t{}\t]", slices );
+            }
             let frac = (gpu.borrow().gpu_milli / (GPU_MILLI / 10)) as usize;
 
             let free = vec!['▒'; frac];
@@ -177,6 +448,22 @@ impl std::fmt::Display for NodeInfoStruct {
             write!(f, "[\t{}\t]", gpu_str )
         };
 
-        self.gpu_rem.iter().try_for_each(write_gpu)
+        self.gpu_rem.iter().try_for_each(write_gpu)?;
+
+        self.pods.iter().try_for_each(|placement| write!(f, "\nPOD:\t{}", placement ))
+    }
+}
+
+impl std::fmt::Display for Placement {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{: <16} on {: <16}", self.pod_name, self.node_name )?;
+
+        if !self.gpus.is_empty() {
+            write!(f, "\tGPU {:?} x {:.2}", self.gpus, self.gpu_milli as f64 / GPU_MILLI as f64 )?;
+        }
+        if let Some(slot) = self.slot {
+            write!(f, " ({})", slot )?;
+        }
+        Ok(())
     }
 }
\ No newline at end of file